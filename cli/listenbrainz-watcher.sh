@@ -111,7 +111,7 @@ change-image() {
 
   image="$new"
   echo >&2 "Displaying $image"
-  cargo run -q -- --dither atkinson --scale fit "$image"
+  cargo run -q -- show --dither atkinson --scale fit "$image"
 }
 
 [[ $(type -t "get-image-$source") == "function" ]] || (echo >&2 "unknown album art source '$source'" && exit 1)
@@ -0,0 +1,49 @@
+//! The last frame sent to each device, so that later frames only need to send what changed.
+
+use std::path::PathBuf;
+
+use anyhow::Context;
+use zerocopy::{IntoBytes, TryFromBytes};
+use ἐννεάς_protocol::{Chunk, geometry::Geometry};
+
+fn path(serial: &str) -> anyhow::Result<PathBuf> {
+    let dir = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").context("HOME not set")?).join(".cache"),
+    };
+    Ok(dir.join("ἐννεάς").join(format!("{serial}.frame")))
+}
+
+/// The chunks of the last frame sent to the device with `serial`, if known.
+pub fn load(serial: &str, geometry: Geometry) -> anyhow::Result<Option<Vec<Chunk>>> {
+    let data = match std::fs::read(path(serial)?) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("reading cached frame"),
+    };
+
+    // A cache written by a different version may not match, so just ignore it
+    Ok(<[Chunk]>::try_ref_from_bytes(&data)
+        .ok()
+        .filter(|chunks| chunks.len() == usize::from(geometry.chunks()))
+        .map(Vec::from))
+}
+
+pub fn store(serial: &str, chunks: &[Chunk]) -> anyhow::Result<()> {
+    let path = path(serial)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).context("creating cache directory")?;
+    }
+    std::fs::write(path, chunks.as_bytes()).context("writing cached frame")
+}
+
+/// Forget the last frame sent to the device with `serial`, for when it has been changed in a way
+/// that isn't cached.
+pub fn clear(serial: &str) -> anyhow::Result<()> {
+    match std::fs::remove_file(path(serial)?) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(err).context("removing cached frame")
+        }
+        _ => Ok(()),
+    }
+}
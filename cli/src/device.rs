@@ -0,0 +1,313 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use futures::FutureExt;
+use indicatif::ProgressBar;
+use nusb::{
+    DeviceInfo, Interface,
+    transfer::{Queue, RequestBuffer},
+};
+use zerocopy::{IntoBytes, TryFromBytes};
+use ἐννεάς_protocol::{
+    Chunk, Command, FrameStatus, Response,
+    event::{Event, LastReset},
+    geometry::Geometry,
+    info,
+    panic::LastPanic,
+    slots::SlotList,
+};
+
+use crate::interrupt;
+
+const COMMANDS_OUT: u8 = 0x02;
+const COMMANDS_IN: u8 = 0x84;
+
+/// How many commands to have in flight before waiting for their responses.
+const WINDOW: usize = 16;
+
+/// How long to wait for the response to a command, long enough for the device to save a frame to
+/// flash before responding.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn find_device() -> anyhow::Result<(DeviceInfo, u8)> {
+    let mut interface_number = None;
+    for device in nusb::list_devices()? {
+        for interface in device.interfaces() {
+            if interface.interface_string() == Some("ἐννεάς-commands") {
+                interface_number = Some(interface.interface_number());
+            }
+        }
+        if let Some(interface_number) = interface_number {
+            return Ok((device, interface_number));
+        }
+    }
+
+    anyhow::bail!("device not found")
+}
+
+pub struct Device {
+    usb_info: DeviceInfo,
+    output: Queue<Vec<u8>>,
+    input: Queue<RequestBuffer>,
+    /// Events received while waiting for responses, kept until asked for.
+    events: VecDeque<Event>,
+}
+
+impl Device {
+    pub fn open() -> anyhow::Result<Self> {
+        let (usb_info, interface_number) = find_device()?;
+        let interface: Interface = usb_info
+            .open()
+            .context("opening usb device")?
+            .detach_and_claim_interface(interface_number)
+            .context("claiming usb interface")?;
+
+        let output = interface.bulk_out_queue(COMMANDS_OUT);
+        let mut input = interface.bulk_in_queue(COMMANDS_IN);
+        while input.pending() < WINDOW {
+            input.submit(RequestBuffer::new(64));
+        }
+
+        Ok(Self {
+            usb_info,
+            output,
+            input,
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn usb_info(&self) -> &DeviceInfo {
+        &self.usb_info
+    }
+
+    /// Receive the next response to a command, setting aside any events for later.
+    fn receive(&mut self) -> anyhow::Result<Response> {
+        loop {
+            match self.receive_any(Some(RESPONSE_TIMEOUT))? {
+                Response::Event(event) => self.events.push_back(event),
+                response => return Ok(response),
+            }
+        }
+    }
+
+    /// Receive the next response or event, giving up after `timeout` if there is one.
+    fn receive_any(&mut self, timeout: Option<Duration>) -> anyhow::Result<Response> {
+        let completion = match timeout {
+            None => futures::executor::block_on(self.input.next_complete()),
+            Some(timeout) => {
+                let deadline = Instant::now() + timeout;
+                loop {
+                    if let Some(completion) = self.input.next_complete().now_or_never() {
+                        break completion;
+                    }
+                    if Instant::now() >= deadline {
+                        anyhow::bail!("timed out waiting for a response from the device");
+                    }
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+        };
+        let data = completion.into_result()?;
+        let response = Response::try_read_from_bytes(&data)
+            .map_err(|err| anyhow::anyhow!("{err}"))
+            .context("parsing response")?;
+        self.input.submit(RequestBuffer::reuse(data, 64));
+        Ok(response)
+    }
+
+    /// Send `commands` to the device, keeping a limited number in flight at once.
+    ///
+    /// Every command but the last must be acknowledged with `Response::Ok`, the response to the
+    /// last command is returned unless it is an error.
+    ///
+    /// If interrupted with Ctrl-C, no more commands are sent and the frame in progress is
+    /// aborted once the commands in flight have been acknowledged.
+    pub fn send(&mut self, commands: &[Command], bar: &ProgressBar) -> anyhow::Result<Response> {
+        let mut pending = commands.iter();
+        let mut submitted = 0;
+        for command in pending.by_ref().take(WINDOW) {
+            if interrupt::interrupted() {
+                break;
+            }
+            self.output.submit(Vec::from(command.as_bytes()));
+            submitted += 1;
+        }
+
+        let mut last = None;
+        for (i, command) in commands.iter().enumerate() {
+            // Fewer than all the commands are submitted when interrupted
+            if i == submitted {
+                break;
+            }
+
+            let response = self.receive()?;
+
+            // The device only responds after reading the command, so this should be complete
+            futures::executor::block_on(self.output.next_complete()).into_result()?;
+
+            match response {
+                Response::Err(err) => {
+                    return Err(
+                        anyhow::Error::new(err).context(format!("device rejected {command:?}"))
+                    );
+                }
+                Response::Ok { .. } => {}
+                _ if i == commands.len() - 1 => {}
+                response => anyhow::bail!("unexpected {response:?} to {command:?}"),
+            }
+
+            if !interrupt::interrupted()
+                && let Some(command) = pending.next()
+            {
+                self.output.submit(Vec::from(command.as_bytes()));
+                submitted += 1;
+            }
+
+            last = Some(response);
+            bar.inc(1);
+        }
+
+        if interrupt::interrupted() {
+            self.abort()?;
+            anyhow::bail!("interrupted");
+        }
+
+        last.context("no commands sent")
+    }
+
+    /// Discard whatever frame the device has in progress, so it can't be shown half sent.
+    fn abort(&mut self) -> anyhow::Result<()> {
+        let command = Command::Abort { _unused: [0; 62] };
+        self.output.submit(Vec::from(command.as_bytes()));
+        let response = self.receive()?;
+        futures::executor::block_on(self.output.next_complete()).into_result()?;
+
+        match response {
+            Response::Ok { .. } => Ok(()),
+            // Older firmware without `Features::ABORT` will still time out the frame eventually
+            Response::Err(_) => Ok(()),
+            response => anyhow::bail!("unexpected {response:?} to {command:?}"),
+        }
+    }
+
+    pub fn query_info(&mut self) -> anyhow::Result<info::DeviceInfo> {
+        match self.send(
+            &[Command::Info { _unused: [0; 62] }],
+            &ProgressBar::hidden(),
+        )? {
+            Response::Info(info) => {
+                anyhow::ensure!(
+                    info.geometry().is_valid(),
+                    "device reported an invalid {:?}",
+                    info.geometry()
+                );
+                Ok(info)
+            }
+            response => anyhow::bail!("unexpected {response:?} to info request"),
+        }
+    }
+
+    pub fn query_slots(&mut self) -> anyhow::Result<SlotList> {
+        match self.send(
+            &[Command::ListSlots { _unused: [0; 62] }],
+            &ProgressBar::hidden(),
+        )? {
+            Response::Slots(slots) => Ok(slots),
+            response => anyhow::bail!("unexpected {response:?} to slots request"),
+        }
+    }
+
+    /// Read back the chunks of the frame the device currently holds.
+    pub fn read_back(
+        &mut self,
+        geometry: Geometry,
+        bar: &ProgressBar,
+    ) -> anyhow::Result<Vec<Chunk>> {
+        let command = Command::ReadBack { _unused: [0; 62] };
+        self.output.submit(Vec::from(command.as_bytes()));
+        futures::executor::block_on(self.output.next_complete()).into_result()?;
+
+        let mut chunks = Vec::with_capacity(usize::from(geometry.chunks()));
+        for counter in 0..geometry.chunks() {
+            // The device gives up if we stop reading for long, so a gap means it stopped early
+            let response = self
+                .receive()
+                .with_context(|| format!("device stopped reading back at chunk {counter}"))?;
+            match response {
+                Response::Chunk(chunk) => chunks.push(chunk),
+                Response::Err(err) => {
+                    return Err(
+                        anyhow::Error::new(err).context(format!("device rejected {command:?}"))
+                    );
+                }
+                response => anyhow::bail!("unexpected {response:?} to {command:?}"),
+            }
+            bar.inc(1);
+        }
+
+        Ok(chunks)
+    }
+
+    /// Wait for the next event from the device.
+    pub fn next_event(&mut self) -> anyhow::Result<Event> {
+        if let Some(event) = self.events.pop_front() {
+            return Ok(event);
+        }
+        match self.receive_any(None)? {
+            Response::Event(event) => Ok(event),
+            response => anyhow::bail!("unexpected {response:?} while waiting for events"),
+        }
+    }
+
+    /// Wait for the first refresh after the device reported having started `refreshes` to
+    /// finish, as returned by [`Self::query_status`] before sending the command that starts it.
+    pub fn wait_refresh(&mut self, refreshes: u16) -> anyhow::Result<()> {
+        // The device sends events after any responses, so events about earlier refreshes can
+        // still arrive after the command starting this one was acknowledged. Refresh numbers
+        // wrap, so any in the half of the range after `refreshes` count as later.
+        let later = |refresh: u16| (1..0x8000).contains(&refresh.wrapping_sub(refreshes));
+        loop {
+            let event = self.next_event()?;
+            match event {
+                Event::Refreshed { refresh, .. } if later(refresh.get()) => return Ok(()),
+                Event::RefreshFailed { refresh, .. } if later(refresh.get()) => {
+                    anyhow::bail!("{event}")
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn query_status(&mut self) -> anyhow::Result<FrameStatus> {
+        match self.send(
+            &[Command::Status { _unused: [0; 62] }],
+            &ProgressBar::hidden(),
+        )? {
+            Response::Status(status) => Ok(status),
+            response => anyhow::bail!("unexpected {response:?} to status request"),
+        }
+    }
+
+    pub fn query_panic(&mut self) -> anyhow::Result<LastPanic> {
+        match self.send(
+            &[Command::LastPanic { _unused: [0; 62] }],
+            &ProgressBar::hidden(),
+        )? {
+            Response::Panic(panic) => Ok(panic),
+            response => anyhow::bail!("unexpected {response:?} to panic request"),
+        }
+    }
+
+    pub fn query_reset(&mut self) -> anyhow::Result<LastReset> {
+        match self.send(
+            &[Command::ResetReason { _unused: [0; 62] }],
+            &ProgressBar::hidden(),
+        )? {
+            Response::Reset(reset) => Ok(reset),
+            response => anyhow::bail!("unexpected {response:?} to reset reason request"),
+        }
+    }
+}
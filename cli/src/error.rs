@@ -0,0 +1,79 @@
+//! Reporting errors with a hint for what to do about them, and an exit code so that scripts can
+//! tell the errors the device reports apart.
+
+use std::process::ExitCode;
+
+use ἐννεάς_protocol::error::DeviceError;
+
+/// Any error not reported by the device, the same as returning an error from `main`.
+const FAILURE: u8 = 1;
+/// The device couldn't parse a command, likely a protocol mismatch.
+const INVALID_COMMAND: u8 = 3;
+/// The device lost track of the frame being sent.
+const LOST_FRAME: u8 = 4;
+/// The data sent doesn't fit the device's frame.
+const INVALID_DATA: u8 = 5;
+/// The frame arrived but its contents were corrupted.
+const CORRUPTED: u8 = 6;
+/// The device can't take commands right now.
+const BUSY: u8 = 7;
+/// The device reported some other error.
+const OTHER: u8 = 8;
+/// The requested slot doesn't exist or has nothing stored in it.
+const NO_SLOT: u8 = 9;
+
+fn classify(err: &DeviceError) -> (u8, &'static str) {
+    match err {
+        DeviceError::InvalidCommand { .. } => (
+            INVALID_COMMAND,
+            "the firmware may not match this version of the cli, try updating both",
+        ),
+        DeviceError::NotStarted { .. } => (
+            LOST_FRAME,
+            "the frame was discarded part way through, send the image again",
+        ),
+        DeviceError::Discarded { .. } => (
+            LOST_FRAME,
+            "the framebuffer holds part of a discarded frame, send a whole image without --region",
+        ),
+        DeviceError::CounterOutOfRange { .. }
+        | DeviceError::RegionOutOfBounds { .. }
+        | DeviceError::InvalidCompressed { .. } => (
+            INVALID_DATA,
+            "the cli and firmware disagree about the frame layout, try updating both",
+        ),
+        DeviceError::InvalidPixel { .. } => (
+            INVALID_DATA,
+            "the image uses colours the panel doesn't have, check the panel's palette with `info`",
+        ),
+        DeviceError::ChecksumMismatch { .. } => (
+            CORRUPTED,
+            "the frame was corrupted in transfer, send the image again with --full",
+        ),
+        DeviceError::Busy { .. } => (
+            BUSY,
+            "wait for the device to finish the frame and refreshing, e.g. with `show --wait`, and try again",
+        ),
+        DeviceError::Other { .. } => (OTHER, "check the device's log interface for details"),
+        DeviceError::OutOfOrder { .. } => (
+            LOST_FRAME,
+            "the device streams chunks straight to the panel, continue with `show --resume`",
+        ),
+        DeviceError::InvalidSlot { .. } | DeviceError::EmptySlot { .. } => {
+            (NO_SLOT, "see which slots the device has with `slot list`")
+        }
+    }
+}
+
+/// Print `err` with a hint if it was reported by the device, returning the exit code to use.
+pub fn report(err: &anyhow::Error) -> ExitCode {
+    eprintln!("Error: {err:?}");
+    match err.downcast_ref::<DeviceError>() {
+        Some(device) => {
+            let (code, hint) = classify(device);
+            eprintln!("\nHint: {hint}");
+            ExitCode::from(code)
+        }
+        None => ExitCode::from(FAILURE),
+    }
+}
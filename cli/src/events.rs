@@ -0,0 +1,43 @@
+//! Formatting device events for scripts to consume.
+
+use std::fmt::Write;
+
+use ἐννεάς_protocol::event::Event;
+
+fn string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `event` as a single line JSON object, with its kind in the `event` field.
+pub fn json(event: &Event) -> String {
+    match event {
+        Event::Booted { reason, .. } => format!(
+            r#"{{"event":"booted","reason":{}}}"#,
+            string(reason.as_ref())
+        ),
+        Event::Refreshed { refresh, .. } => {
+            format!(r#"{{"event":"refreshed","refresh":{refresh}}}"#)
+        }
+        Event::RefreshFailed { refresh, msg } => format!(
+            r#"{{"event":"refresh-failed","refresh":{refresh},"message":{}}}"#,
+            string(msg.to_str().unwrap_or("<invalid message>"))
+        ),
+        Event::FrameDiscarded { .. } => r#"{"event":"frame-discarded"}"#.to_owned(),
+        Event::Panicked { msg } => format!(
+            r#"{{"event":"panicked","message":{}}}"#,
+            string(msg.to_str().unwrap_or("<invalid message>"))
+        ),
+    }
+}
@@ -0,0 +1,43 @@
+//! Ctrl-C handling, so that an interrupted transfer can be aborted on the device instead of being
+//! left half sent.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Context;
+use nix::{
+    libc,
+    sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction},
+};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle(_: libc::c_int) {
+    // A second Ctrl-C exits immediately, in case the device has stopped responding
+    if INTERRUPTED.swap(true, Ordering::Relaxed) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Record Ctrl-C to be checked with [`interrupted`] instead of exiting.
+pub fn install() -> anyhow::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only touches an atomic and calls `_exit`, both are signal safe
+    unsafe { sigaction(Signal::SIGINT, &action) }.context("installing Ctrl-C handler")?;
+    Ok(())
+}
+
+/// Go back to exiting on Ctrl-C, for once there's nothing left to abort.
+pub fn uninstall() -> anyhow::Result<()> {
+    let action = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+    // SAFETY: restoring the default handler is always safe
+    unsafe { sigaction(Signal::SIGINT, &action) }.context("removing Ctrl-C handler")?;
+    Ok(())
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
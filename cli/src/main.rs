@@ -3,30 +3,26 @@ extern crate ennead_protocol as ἐννεάς_protocol;
 use anyhow::Context;
 use clap::{Parser, ValueEnum};
 use dither::Dither as _;
-use image::{ImageReader, imageops::FilterType};
+use image::{ImageReader, imageops::FilterType, math::Rect};
 use indicatif::{ProgressBar, ProgressStyle};
-use nusb::DeviceInfo;
-use zerocopy::IntoBytes;
-use ἐννεάς_protocol::{Command, HEIGHT, WIDTH, image::PALETTE};
-
-fn find_device() -> anyhow::Result<(DeviceInfo, u8)> {
-    let mut interface_number = None;
-    for device in nusb::list_devices()? {
-        for interface in device.interfaces() {
-            if interface.interface_string() == Some("ἐννεάς-commands") {
-                interface_number = Some(interface.interface_number());
-            }
-        }
-        if let Some(interface_number) = interface_number {
-            return Ok((device, interface_number));
-        }
-    }
-
-    anyhow::bail!("device not found")
-}
+use ἐννεάς_protocol::{
+    Chunk, Command, Response,
+    event::ResetReason,
+    frame_checksum,
+    geometry::Geometry,
+    info::{DeviceInfo, Features, PROTOCOL_VERSION, Palette},
+    slots::SlideshowConfig,
+};
+
+mod cache;
+mod device;
+mod error;
+mod events;
+mod interrupt;
 
 fn dither_dither(
     image: image::RgbImage,
+    palette: &[image::Rgb<u8>],
     ditherer: dither::ditherer::Ditherer<'static>,
 ) -> image::RgbImage {
     let img = dither::Img::new(
@@ -37,7 +33,10 @@ fn dither_dither(
     )
     .unwrap();
 
-    let palette = PALETTE.map(|image::Rgb([r, g, b])| dither::color::RGB(r, g, b));
+    let palette: Vec<_> = palette
+        .iter()
+        .map(|&image::Rgb([r, g, b])| dither::color::RGB(r, g, b))
+        .collect();
 
     let img = ditherer.dither(img, dither::color::palette::quantize(&palette));
 
@@ -51,10 +50,10 @@ fn dither_dither(
     .unwrap()
 }
 
-fn bayer(image: image::RgbImage) -> image::RgbImage {
+fn bayer(image: image::RgbImage, palette: &[image::Rgb<u8>]) -> image::RgbImage {
     use image_effects::effect::Effect;
 
-    let palette = PALETTE
+    let palette = palette
         .iter()
         .map(|&image::Rgb([r, g, b])| {
             palette::rgb::Srgb::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0)
@@ -76,7 +75,7 @@ fn bayer(image: image::RgbImage) -> image::RgbImage {
     .unwrap()
 }
 
-fn blue_noise(mut image: image::RgbImage) -> image::RgbImage {
+fn blue_noise(mut image: image::RgbImage, palette: &[image::Rgb<u8>]) -> image::RgbImage {
     fn dist(x: &image::Rgb<u8>, y: &image::Rgb<u8>) -> u32 {
         (x.0[0].abs_diff(y.0[0]) as u32).pow(2)
             + (x.0[1].abs_diff(y.0[1]) as u32).pow(2)
@@ -98,7 +97,7 @@ fn blue_noise(mut image: image::RgbImage) -> image::RgbImage {
             let offset = pixel[c] as i16 - noise[c] as i16 + 128 as i16;
             offset.clamp(0, 255) as u8
         }));
-        *pixel = *PALETTE.iter().min_by_key(|p| dist(&dith, p)).unwrap();
+        *pixel = *palette.iter().min_by_key(|p| dist(&dith, p)).unwrap();
     }
 
     image
@@ -117,16 +116,21 @@ enum Dither {
 }
 
 impl Dither {
-    fn apply(self, image: image::RgbImage) -> image::RgbImage {
+    fn apply(self, image: image::RgbImage, palette: Palette) -> image::RgbImage {
+        let palette = &palette.rgb();
         match self {
-            Self::Atkinson => dither_dither(image, dither::ditherer::ATKINSON),
-            Self::Burkes => dither_dither(image, dither::ditherer::BURKES),
-            Self::FloydSteinberg => dither_dither(image, dither::ditherer::FLOYD_STEINBERG),
-            Self::JarvisJudiceNinke => dither_dither(image, dither::ditherer::JARVIS_JUDICE_NINKE),
-            Self::Sierra3 => dither_dither(image, dither::ditherer::SIERRA_3),
-            Self::Stucki => dither_dither(image, dither::ditherer::STUCKI),
-            Self::Bayer => bayer(image),
-            Self::BlueNoise => blue_noise(image),
+            Self::Atkinson => dither_dither(image, palette, dither::ditherer::ATKINSON),
+            Self::Burkes => dither_dither(image, palette, dither::ditherer::BURKES),
+            Self::FloydSteinberg => {
+                dither_dither(image, palette, dither::ditherer::FLOYD_STEINBERG)
+            }
+            Self::JarvisJudiceNinke => {
+                dither_dither(image, palette, dither::ditherer::JARVIS_JUDICE_NINKE)
+            }
+            Self::Sierra3 => dither_dither(image, palette, dither::ditherer::SIERRA_3),
+            Self::Stucki => dither_dither(image, palette, dither::ditherer::STUCKI),
+            Self::Bayer => bayer(image, palette),
+            Self::BlueNoise => blue_noise(image, palette),
         }
     }
 }
@@ -140,6 +144,150 @@ enum Scale {
 
 #[derive(Parser)]
 struct Args {
+    #[command(subcommand)]
+    command: Subcommand,
+}
+
+#[derive(clap::Subcommand)]
+enum Subcommand {
+    /// Send an image to the display
+    Show(ShowArgs),
+
+    /// Print information about the connected device
+    Info,
+
+    /// Print events from the device as they happen
+    Events(EventsArgs),
+
+    /// Save the frame the device currently holds as an image
+    Screenshot(ScreenshotArgs),
+
+    /// Manage frames stored in the device's flash
+    #[command(subcommand)]
+    Slot(SlotCommand),
+
+    /// Have the device cycle through stored frames by itself
+    Slideshow(SlideshowArgs),
+
+    /// Reset the device
+    Reboot(RebootArgs),
+
+    /// Print the message from the device's last panic, if that's why it last reset
+    Panic,
+
+    /// Print why the device last reset
+    ResetReason,
+}
+
+#[derive(clap::Args)]
+struct RebootArgs {
+    /// Reboot into the USB bootloader to flash new firmware, as if BOOTSEL was held
+    #[arg(long)]
+    bootloader: bool,
+}
+
+#[derive(clap::Args)]
+struct SlideshowArgs {
+    /// How long to show each frame, e.g. `90s`, `30m` or `1h30m`
+    #[arg(long, value_parser = parse_interval, required_unless_present = "stop")]
+    interval: Option<u32>,
+
+    /// Slots to cycle through, e.g. `0-5` or `0,2,4`, defaults to every slot
+    #[arg(long, value_parser = parse_slots)]
+    slots: Option<SlotSet>,
+
+    /// Stop the slideshow, leaving the current frame on the panel
+    #[arg(long, conflicts_with_all = ["interval", "slots"])]
+    stop: bool,
+}
+
+#[derive(Clone)]
+struct SlotSet(Vec<u8>);
+
+fn parse_interval(s: &str) -> anyhow::Result<u32> {
+    let mut total = 0u32;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .context("missing unit, one of `s`, `m`, `h` or `d`")?;
+        let (number, unit) = rest.split_at(digits);
+        let mut unit = unit.chars();
+        let scale = match unit.next() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 60 * 60,
+            Some('d') => 24 * 60 * 60,
+            _ => anyhow::bail!("unknown unit, expected one of `s`, `m`, `h` or `d`"),
+        };
+        rest = unit.as_str();
+        total = number
+            .parse::<u32>()?
+            .checked_mul(scale)
+            .and_then(|seconds| total.checked_add(seconds))
+            .context("interval too long")?;
+    }
+    anyhow::ensure!(total > 0, "interval must be longer than 0s");
+    Ok(total)
+}
+
+fn parse_slots(s: &str) -> anyhow::Result<SlotSet> {
+    let mut slots = Vec::new();
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (start.parse::<u8>()?, end.parse()?);
+                anyhow::ensure!(start <= end, "slot range {part} is backwards");
+                slots.extend(start..=end);
+            }
+            None => slots.push(part.parse()?),
+        }
+    }
+    if let Some(slot) = slots.iter().find(|&&slot| slot >= SlideshowConfig::SLOTS) {
+        anyhow::bail!(
+            "slot {slot} is too high, a slideshow can only use slots 0 to {}",
+            SlideshowConfig::SLOTS - 1
+        );
+    }
+    Ok(SlotSet(slots))
+}
+
+#[derive(clap::Subcommand)]
+enum SlotCommand {
+    /// List the slots and whether they hold a frame
+    List,
+
+    /// Save the frame the device currently holds into a slot
+    Save { slot: u8 },
+
+    /// Delete the frame stored in a slot
+    Delete { slot: u8 },
+
+    /// Show the frame stored in a slot
+    Show {
+        slot: u8,
+
+        /// Wait until the panel has finished refreshing before exiting
+        #[arg(long)]
+        wait: bool,
+    },
+}
+
+#[derive(clap::Args)]
+struct ScreenshotArgs {
+    /// Image file to write
+    output: String,
+}
+
+#[derive(clap::Args)]
+struct EventsArgs {
+    /// Print each event as a line of JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args)]
+struct ShowArgs {
     /// Image to send to the display
     image: String,
 
@@ -150,39 +298,93 @@ struct Args {
     /// Scaling to apply to fit image to frame
     #[arg(long, value_enum)]
     scale: Scale,
+
+    /// Only update this part of the frame, as `WIDTHxHEIGHT+X+Y`, leaving the rest untouched
+    #[arg(long, value_parser = parse_region)]
+    region: Option<Rect>,
+
+    /// Send the whole frame, even if only part of it changed since the last image sent
+    #[arg(long)]
+    full: bool,
+
+    /// Carry on from where an interrupted transfer of the same image stopped, if the device still
+    /// has it in progress
+    #[arg(long, conflicts_with = "region")]
+    resume: bool,
+
+    /// Wait until the panel has finished refreshing before exiting
+    #[arg(long)]
+    wait: bool,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+fn parse_region(s: &str) -> anyhow::Result<Rect> {
+    let (size, position) = s.split_once('+').context("missing position")?;
+    let (width, height) = size.split_once('x').context("missing height")?;
+    let (x, y) = position.split_once('+').context("missing y position")?;
+    Ok(Rect {
+        x: x.parse()?,
+        y: y.parse()?,
+        width: width.parse()?,
+        height: height.parse()?,
+    })
+}
 
-    let spinner = ProgressStyle::with_template("{prefix:>40.cyan} {spinner} {msg}")?;
-    let success = ProgressStyle::with_template("{prefix:>40.green} {spinner} {msg}")?;
-    let bar_style = ProgressStyle::with_template(
-        "{prefix:>40.cyan} {spinner} [{bar:27}] {pos:>9}/{len:9}  {per_sec} {elapsed:>4}/{eta:4}",
-    )?;
+struct Styles {
+    spinner: ProgressStyle,
+    success: ProgressStyle,
+    bar: ProgressStyle,
+}
 
+impl Styles {
+    fn new() -> anyhow::Result<Self> {
+        Ok(Self {
+            spinner: ProgressStyle::with_template("{prefix:>40.cyan} {spinner} {msg}")?,
+            success: ProgressStyle::with_template("{prefix:>40.green} {spinner} {msg}")?,
+            bar: ProgressStyle::with_template(
+                "{prefix:>40.cyan} {spinner} [{bar:27}] {pos:>9}/{len:9}  {per_sec} {elapsed:>4}/{eta:4}",
+            )?,
+        })
+    }
+}
+
+/// Find and open the device, checking that it speaks the same protocol version as us.
+fn open_device(styles: &Styles) -> anyhow::Result<(device::Device, DeviceInfo)> {
     let bar = ProgressBar::no_length()
-        .with_style(spinner.clone())
+        .with_style(styles.spinner.clone())
         .with_prefix("finding ἐννεάς device");
-    let (device, interface_number) = find_device()?;
-    let interface = device
-        .open()
-        .context("opening usb device")?
-        .detach_and_claim_interface(interface_number)
-        .context("claiming usb interface")?;
-    bar.with_style(success.clone())
+    let mut device = device::Device::open()?;
+    let info = device
+        .query_info()
+        .context("querying device info, its firmware may be older than this cli")?;
+    bar.with_style(styles.success.clone())
         .with_prefix("found device")
         .finish_with_message(format!(
-            "{}/{} {}",
-            device.manufacturer_string().unwrap_or("<unknown>"),
-            device.product_string().unwrap_or("<unknown>"),
-            device.serial_number().unwrap_or("<unknown>")
+            "{}/{} {} (firmware {})",
+            device
+                .usb_info()
+                .manufacturer_string()
+                .unwrap_or("<unknown>"),
+            device.usb_info().product_string().unwrap_or("<unknown>"),
+            device.usb_info().serial_number().unwrap_or("<unknown>"),
+            info.firmware_version().unwrap_or("<unknown>"),
         ));
 
-    let bar = ProgressBar::no_length()
-        .with_style(spinner.clone())
-        .with_prefix("loading image")
-        .with_message(args.image.clone());
+    if info.protocol_version() != PROTOCOL_VERSION {
+        anyhow::bail!(
+            "device speaks protocol version {}, but this cli speaks version {PROTOCOL_VERSION}",
+            info.protocol_version(),
+        );
+    }
+
+    Ok((device, info))
+}
+
+fn load_image(
+    args: &ShowArgs,
+    geometry: Geometry,
+    palette: Palette,
+) -> anyhow::Result<image::RgbImage> {
+    let (width, height) = (u32::from(geometry.width()), u32::from(geometry.height()));
 
     let image = ImageReader::open(&args.image)?
         .with_guessed_format()?
@@ -190,24 +392,24 @@ fn main() -> anyhow::Result<()> {
     image.save("/tmp/ἐννεάς.original.png").unwrap();
 
     let image = match args.scale {
-        Scale::Fit => image.resize(WIDTH, HEIGHT, FilterType::CatmullRom),
-        Scale::Fill => image.resize_to_fill(WIDTH, HEIGHT, FilterType::CatmullRom),
-        Scale::Stretch => image.resize_exact(WIDTH, HEIGHT, FilterType::CatmullRom),
+        Scale::Fit => image.resize(width, height, FilterType::CatmullRom),
+        Scale::Fill => image.resize_to_fill(width, height, FilterType::CatmullRom),
+        Scale::Stretch => image.resize_exact(width, height, FilterType::CatmullRom),
     }
     .to_rgb8();
     image.save("/tmp/ἐννεάς.resized.png").unwrap();
 
-    let image = args.dither.apply(image);
+    let image = args.dither.apply(image, palette);
     image.save("/tmp/ἐννεάς.dithered.png").unwrap();
 
     let image = match args.scale {
         Scale::Fit => {
-            let mut base = image::RgbImage::from_pixel(WIDTH, HEIGHT, image::Rgb([255, 255, 255]));
+            let mut base = image::RgbImage::from_pixel(width, height, image::Rgb([255, 255, 255]));
             image::imageops::overlay(
                 &mut base,
                 &image,
-                i64::from((WIDTH - image.width()) / 2),
-                i64::from((HEIGHT - image.height()) / 2),
+                i64::from((width - image.width()) / 2),
+                i64::from((height - image.height()) / 2),
             );
             base.save("/tmp/ἐννεάς.padded.png").unwrap();
             base
@@ -215,34 +417,511 @@ fn main() -> anyhow::Result<()> {
         Scale::Fill | Scale::Stretch => image,
     };
 
-    let image = {
-        let mut image = image;
-        image::imageops::rotate180_in_place(&mut image);
-        image
+    let mut image = image;
+    image::imageops::rotate180_in_place(&mut image);
+    Ok(image)
+}
+
+fn show(args: ShowArgs, styles: &Styles) -> anyhow::Result<()> {
+    let (mut device, info) = open_device(styles)?;
+
+    let geometry = info.geometry();
+    let (width, height) = (u32::from(geometry.width()), u32::from(geometry.height()));
+
+    if let Some(region) = args.region {
+        if !info.features().contains(Features::REGION) {
+            anyhow::bail!("device does not support region updates");
+        }
+        if region.x + region.width > width || region.y + region.height > height {
+            anyhow::bail!("region extends outside {width}x{height} frame");
+        }
+    }
+
+    if args.resume && !info.features().contains(Features::RESUME) {
+        anyhow::bail!("device does not support resuming transfers");
+    }
+
+    if args.wait && !info.features().contains(Features::EVENTS) {
+        anyhow::bail!("device does not support waiting for refreshes");
+    }
+
+    let bar = ProgressBar::no_length()
+        .with_style(styles.spinner.clone())
+        .with_prefix("loading image")
+        .with_message(args.image.clone());
+
+    let palette = info.palette();
+    let image = load_image(&args, geometry, palette)?;
+    let compress = info.features().contains(Features::COMPRESSED);
+    let full = Command::from_image(&image, geometry, palette, compress);
+
+    // Without a serial number there's no way to tell which frame the device has
+    let serial = device.usb_info().serial_number().map(str::to_owned);
+    let previous = match &serial {
+        Some(serial) if !args.full && info.features().contains(Features::DELTA) => {
+            cache::load(serial, geometry)?
+        }
+        _ => None,
+    };
+
+    // Taken before sending, so that the refresh the frame starts can be told apart from earlier
+    // ones
+    let refreshes = if args.wait {
+        Some(device.query_status()?.refreshes())
+    } else {
+        None
+    };
+
+    let resume_from = if args.resume {
+        let status = device.query_status()?;
+        if !status.in_progress() {
+            bar.println("device has no frame in progress, sending the whole image");
+        }
+        status.in_progress().then_some(status.contiguous())
+    } else {
+        None
     };
-    let commands = Command::from_image(&image);
 
-    bar.with_style(success.clone())
+    let commands = match (args.region, resume_from, previous) {
+        // The image has been rotated to match the panel, so the region must be too
+        (Some(region), _, _) => Command::from_image_region(
+            &image,
+            geometry,
+            palette,
+            Rect {
+                x: width - region.x - region.width,
+                y: height - region.y - region.height,
+                ..region
+            },
+        ),
+        (None, Some(contiguous), _) => {
+            bar.println(format!("resuming from chunk {contiguous}"));
+            Command::from_image_resume(&image, geometry, palette, contiguous, compress)
+        }
+        (None, None, Some(previous)) => {
+            Command::from_image_delta(&image, geometry, palette, &previous, compress)
+        }
+        (None, None, None) => full.clone(),
+    };
+
+    bar.with_style(styles.success.clone())
         .with_prefix("loaded image")
         .finish();
 
     let bar = ProgressBar::new(u64::try_from(commands.len())?)
-        .with_style(bar_style.clone())
+        .with_style(styles.bar.clone())
         .with_prefix("sending commands");
 
-    let mut output = interface.bulk_out_queue(0x02);
-    for command in &commands {
-        output.submit(Vec::from(command.as_bytes()));
+    // From here on the device has a frame in progress, so Ctrl-C needs to abort it
+    interrupt::install()?;
+
+    let mut response = device.send(&commands, &bar)?;
+
+    let mut previously_missing = None;
+    while let Response::Incomplete(missing) = response {
+        if !info.features().contains(Features::RETRANSMIT) {
+            bar.abandon();
+            anyhow::bail!(
+                "device is missing {} chunks and can't take them retransmitted, restart the \
+                 transfer by sending the image again",
+                missing.total()
+            );
+        }
+
+        // Only a limited number of missing ranges fit in a response, so keep retransmitting as
+        // long as the device is making progress.
+        if previously_missing.is_some_and(|previous| missing.total() >= previous) {
+            bar.abandon();
+            anyhow::bail!(
+                "device is still missing {} chunks after retransmitting",
+                missing.total()
+            );
+        }
+        previously_missing = Some(missing.total());
+
+        bar.println(format!(
+            "device is missing {} chunks, retransmitting",
+            missing.total()
+        ));
+
+        // The device may have fallen back from a delta to a whole frame, so retransmit from the
+        // whole frame's commands
+        let retransmit: Vec<Command> = full
+            .iter()
+            .filter(|command| match command {
+                Command::Chunk(chunk) => missing
+                    .ranges()
+                    .any(|range| range.contains(&chunk.counter())),
+                Command::Compressed(compressed) => {
+                    let counters = compressed.counters();
+                    missing
+                        .ranges()
+                        .any(|range| range.start < counters.end && counters.start < range.end)
+                }
+                Command::End { .. } => true,
+                _ => false,
+            })
+            .copied()
+            .collect();
+
+        bar.inc_length(u64::try_from(retransmit.len())?);
+        response = device.send(&retransmit, &bar)?;
     }
 
-    while output.pending() > 0 {
-        futures::executor::block_on(output.next_complete()).into_result()?;
-        bar.inc(1);
+    if let Some(serial) = &serial {
+        match args.region {
+            Some(_) => cache::clear(serial)?,
+            None => cache::store(serial, &Chunk::from_image(&image, geometry, palette))?,
+        }
     }
 
-    bar.with_style(success.clone())
+    // The frame is complete, so there's nothing to abort any more
+    interrupt::uninstall()?;
+
+    let Some(refreshes) = refreshes else {
+        bar.with_style(styles.success.clone())
+            .with_prefix("sent commands")
+            .finish_with_message("image should be refreshing now");
+        return Ok(());
+    };
+
+    bar.with_style(styles.success.clone())
         .with_prefix("sent commands")
-        .finish_with_message("image should be refreshing now");
+        .finish();
+
+    wait_refresh(&mut device, refreshes, styles)
+}
+
+/// Wait for the first refresh after `refreshes` to finish, see [`device::Device::wait_refresh`].
+fn wait_refresh(
+    device: &mut device::Device,
+    refreshes: u16,
+    styles: &Styles,
+) -> anyhow::Result<()> {
+    let bar = ProgressBar::new_spinner()
+        .with_style(styles.spinner.clone())
+        .with_prefix("refreshing panel");
+    bar.enable_steady_tick(std::time::Duration::from_millis(100));
+    if let Err(err) = device.wait_refresh(refreshes) {
+        bar.abandon();
+        return Err(err);
+    }
+    bar.with_style(styles.success.clone())
+        .with_prefix("refreshed panel")
+        .finish();
+
+    Ok(())
+}
+
+fn info(styles: &Styles) -> anyhow::Result<()> {
+    let (_, info) = open_device(styles)?;
+
+    let geometry = info.geometry();
+    println!("protocol version: {}", info.protocol_version());
+    println!(
+        "firmware version: {}",
+        info.firmware_version().unwrap_or("<unknown>")
+    );
+    println!("panel: {}", info.panel().as_ref());
+    println!(
+        "resolution: {}×{}, {} bits per pixel",
+        geometry.width(),
+        geometry.height(),
+        geometry.depth().bits()
+    );
+    println!(
+        "palette: {} {:?}",
+        info.palette().as_ref(),
+        info.palette().colors()
+    );
+    println!(
+        "features: {}",
+        info.features().names().collect::<Vec<_>>().join(", ")
+    );
+
+    Ok(())
+}
+
+fn events(args: EventsArgs, styles: &Styles) -> anyhow::Result<()> {
+    let (mut device, info) = open_device(styles)?;
+
+    if !info.features().contains(Features::EVENTS) {
+        anyhow::bail!("device does not support events");
+    }
+
+    loop {
+        let event = device.next_event()?;
+        if args.json {
+            println!("{}", events::json(&event));
+        } else {
+            println!("{event}");
+        }
+    }
+}
+
+fn screenshot(args: ScreenshotArgs, styles: &Styles) -> anyhow::Result<()> {
+    let (mut device, info) = open_device(styles)?;
+
+    if !info.features().contains(Features::READBACK) {
+        anyhow::bail!("device does not support reading back the frame");
+    }
+
+    let geometry = info.geometry();
+    let bar = ProgressBar::new(u64::from(geometry.chunks()))
+        .with_style(styles.bar.clone())
+        .with_prefix("reading frame");
+    let chunks = device.read_back(geometry, &bar)?;
+    bar.with_style(styles.success.clone())
+        .with_prefix("read frame")
+        .finish();
+
+    let mut image = Chunk::to_image(&chunks, geometry, info.palette())?;
+    // Images are rotated to match the panel when sent, so undo that
+    image::imageops::rotate180_in_place(&mut image);
+    image
+        .save(&args.output)
+        .with_context(|| format!("writing {}", args.output))?;
+
+    Ok(())
+}
+
+fn slot(command: SlotCommand, styles: &Styles) -> anyhow::Result<()> {
+    let (mut device, info) = open_device(styles)?;
+
+    if !info.features().contains(Features::SLOTS) {
+        anyhow::bail!("device does not support storing frames");
+    }
+
+    let wait = matches!(command, SlotCommand::Show { wait: true, .. });
+    if wait && !info.features().contains(Features::EVENTS) {
+        anyhow::bail!("device does not support waiting for refreshes");
+    }
+
+    let command = match command {
+        SlotCommand::List => {
+            let serial = device.usb_info().serial_number().map(str::to_owned);
+            let current = match &serial {
+                Some(serial) => cache::load(serial, info.geometry())?
+                    .map(|chunks| frame_checksum(chunks.iter().copied())),
+                None => None,
+            };
+            for (slot, info) in device.query_slots()?.slots().iter().enumerate() {
+                match info.checksum() {
+                    Some(checksum) if Some(checksum) == current => {
+                        println!("{slot}: frame {checksum:#010x} (last image sent)")
+                    }
+                    Some(checksum) => println!("{slot}: frame {checksum:#010x}"),
+                    None => println!("{slot}: empty"),
+                }
+            }
+            return Ok(());
+        }
+        SlotCommand::Save { .. } if info.features().contains(Features::STREAMING) => {
+            anyhow::bail!("device streams frames straight to the panel, so it can't save them");
+        }
+        SlotCommand::Save { slot } => Command::SaveSlot {
+            slot,
+            _unused: [0; 61],
+        },
+        SlotCommand::Delete { slot } => Command::DeleteSlot {
+            slot,
+            _unused: [0; 61],
+        },
+        SlotCommand::Show { slot, .. } => Command::ShowSlot {
+            slot,
+            _unused: [0; 61],
+        },
+    };
+
+    let refreshes = if wait {
+        Some(device.query_status()?.refreshes())
+    } else {
+        None
+    };
+
+    device.send(&[command], &ProgressBar::hidden())?;
+
+    if let Command::ShowSlot { .. } = command {
+        // The device's frame no longer matches the last image sent
+        if let Some(serial) = device.usb_info().serial_number() {
+            cache::clear(serial)?;
+        }
+    }
+
+    if let Some(refreshes) = refreshes {
+        wait_refresh(&mut device, refreshes, styles)?;
+    }
+
+    Ok(())
+}
+
+fn slideshow(args: SlideshowArgs, styles: &Styles) -> anyhow::Result<()> {
+    let (mut device, info) = open_device(styles)?;
+
+    if !info.features().contains(Features::SLIDESHOW) {
+        anyhow::bail!("device does not support slideshows");
+    }
+
+    let config = match args.interval {
+        Some(interval) if !args.stop => {
+            let count = u8::try_from(device.query_slots()?.slots().len())?;
+            let slots = match args.slots {
+                Some(SlotSet(slots)) => slots,
+                None => (0..count).collect(),
+            };
+            if let Some(slot) = slots.iter().find(|&&slot| slot >= count) {
+                anyhow::bail!("no slot {slot}, the device has {count} slots");
+            }
+            SlideshowConfig::new(interval, slots)?
+        }
+        _ => SlideshowConfig::STOPPED,
+    };
+
+    device.send(&[Command::Slideshow(config)], &ProgressBar::hidden())?;
 
     Ok(())
 }
+
+fn reboot(args: RebootArgs, styles: &Styles) -> anyhow::Result<()> {
+    let (mut device, info) = open_device(styles)?;
+
+    if !info.features().contains(Features::REBOOT) {
+        anyhow::bail!("device does not support rebooting");
+    }
+
+    let command = Command::Reboot {
+        bootloader: args.bootloader,
+        _unused: [0; 61],
+    };
+    device.send(&[command], &ProgressBar::hidden())?;
+
+    // The framebuffer doesn't survive a reset
+    if let Some(serial) = device.usb_info().serial_number() {
+        cache::clear(serial)?;
+    }
+
+    Ok(())
+}
+
+fn panic(styles: &Styles) -> anyhow::Result<()> {
+    let (mut device, info) = open_device(styles)?;
+
+    if !info.features().contains(Features::PANIC) {
+        anyhow::bail!("device does not support reporting panics");
+    }
+
+    match device.query_panic()?.message() {
+        Some(message) => println!("last panic: {message}"),
+        None => println!("device didn't panic before it last reset"),
+    }
+
+    Ok(())
+}
+
+fn reset_reason(styles: &Styles) -> anyhow::Result<()> {
+    let (mut device, info) = open_device(styles)?;
+
+    if !info.features().contains(Features::WATCHDOG) {
+        anyhow::bail!("device does not support reporting why it reset");
+    }
+
+    let reason = device.query_reset()?.reason();
+    match reason {
+        // Show what the panic was, so this agrees with the `panic` subcommand
+        ResetReason::Panic if info.features().contains(Features::PANIC) => {
+            let message = device.query_panic()?;
+            println!(
+                "last reset: {} ({})",
+                reason.as_ref(),
+                message.message().unwrap_or("<no message>")
+            );
+        }
+        _ => println!("last reset: {}", reason.as_ref()),
+    }
+
+    Ok(())
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
+    let styles = Styles::new()?;
+
+    match args.command {
+        Subcommand::Show(args) => show(args, &styles),
+        Subcommand::Info => info(&styles),
+        Subcommand::Events(args) => events(args, &styles),
+        Subcommand::Screenshot(args) => screenshot(args, &styles),
+        Subcommand::Slot(command) => slot(command, &styles),
+        Subcommand::Slideshow(args) => slideshow(args, &styles),
+        Subcommand::Reboot(args) => reboot(args, &styles),
+        Subcommand::Panic => panic(&styles),
+        Subcommand::ResetReason => reset_reason(&styles),
+    }
+}
+
+fn main() -> std::process::ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) => error::report(&err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SlotSet, parse_interval, parse_region, parse_slots};
+
+    #[test]
+    fn interval() {
+        assert_eq!(parse_interval("90s").unwrap(), 90);
+        assert_eq!(parse_interval("1h30m").unwrap(), 90 * 60);
+        assert_eq!(parse_interval("2d").unwrap(), 2 * 24 * 60 * 60);
+        assert!(parse_interval("").is_err());
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval("10").is_err());
+        assert!(parse_interval("10x").is_err());
+        assert!(parse_interval("50000d").is_err());
+    }
+
+    #[test]
+    fn slots() {
+        let SlotSet(slots) = parse_slots("0,2-4,15").unwrap();
+        assert_eq!(slots, [0, 2, 3, 4, 15]);
+        let SlotSet(slots) = parse_slots("3-3").unwrap();
+        assert_eq!(slots, [3]);
+    }
+
+    #[test]
+    fn slots_backwards() {
+        assert!(parse_slots("4-2").is_err());
+    }
+
+    #[test]
+    fn slots_out_of_range() {
+        assert!(parse_slots("16").is_err());
+        assert!(parse_slots("10-20").is_err());
+        assert!(parse_slots("256").is_err());
+        assert!(parse_slots("-1").is_err());
+    }
+
+    #[test]
+    fn region() {
+        let region = parse_region("100x50+10+20").unwrap();
+        assert_eq!(
+            (region.x, region.y, region.width, region.height),
+            (10, 20, 100, 50)
+        );
+    }
+
+    #[test]
+    fn region_malformed() {
+        for s in [
+            "100x50",
+            "100x50+10",
+            "100+10+20",
+            "100x+10+20",
+            "ax50+10+20",
+            "100x50+-1+0",
+        ] {
+            assert!(parse_region(s).is_err(), "{s}");
+        }
+    }
+}
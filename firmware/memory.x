@@ -1,6 +1,7 @@
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    /* The rest of the 2048K flash holds saved frames, see src/flash.rs */
+    FLASH : ORIGIN = 0x10000100, LENGTH = 508K - 0x100
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
@@ -1,35 +1,133 @@
-use embedded_hal::digital::OutputPin;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embedded_hal::{
+    digital::{ErrorType, InputPin, OutputPin},
+    spi::SpiDevice,
+};
 use embedded_hal_bus::spi::{ExclusiveDevice, NoDelay};
-use panic_halt as _;
+#[cfg(not(feature = "streaming"))]
+use ἐννεάς_protocol::frame_checksum;
 use ἐννεάς_protocol::Chunk;
+#[cfg(feature = "streaming")]
+use ἐννεάς_protocol::{checksum::Crc32, embedded::OctBytes};
 
 use waveshare_rp2040_epaper_73::{
-    hal::{pac, spi, Timer},
+    hal::{
+        multicore::{Multicore, Stack},
+        pac,
+        sio::{Sio, SioFifo},
+        spi,
+        watchdog::Watchdog,
+        Timer,
+    },
     EpdBusy, EpdDc, EpdReset, EpdSpiClock, EpdSpiCs, EpdSpiTx, LedActivity,
 };
 
-use embedded_graphics::draw_target::DrawTarget;
+#[cfg(not(feature = "streaming"))]
+use embedded_graphics::{draw_target::DrawTarget, Pixel};
 
-use epd_waveshare::{
-    color::OctColor,
-    epd7in3f::{Display7in3f, Epd7in3f},
-    prelude::WaveshareDisplay,
-};
+use crate::GEOMETRY;
+
+use epd_waveshare::{epd7in3f::Epd7in3f, prelude::WaveshareDisplay};
+
+#[cfg(not(feature = "streaming"))]
+use epd_waveshare::{color::OctColor, epd7in3f::Display7in3f};
 
 type Spi = ExclusiveDevice<
     spi::Spi<spi::Enabled, pac::SPI1, (EpdSpiTx, EpdSpiClock), 8>,
     EpdSpiCs,
     NoDelay,
 >;
-type Device = Epd7in3f<Spi, EpdBusy, EpdDc, EpdReset, Timer>;
+type Device = Epd7in3f<Spi, Busy, EpdDc, EpdReset, Timer>;
 
-pub struct Display {
+static mut CORE1_STACK: Stack<4096> = Stack::new();
+
+/// Operations core 0 sends core 1, each followed by its arguments.
+const BEGIN: u32 = 1;
+/// Takes a pointer and length of the data.
+const DATA: u32 = 2;
+const SHOW: u32 = 3;
+
+/// Sent by core 1 once it's ready, and after each operation.
+const DONE: u32 = 1;
+/// Sent by core 1 instead of [`DONE`] after an operation where the panel stayed busy too long.
+const BUSY_TIMEOUT: u32 = 2;
+
+/// How long the panel can stay busy before it's assumed to be stuck, in timer ticks. A refresh
+/// normally keeps it busy for around 30 seconds.
+const BUSY_LIMIT: u64 = 60_000_000;
+
+/// How long core 0 waits for core 1 to finish the operations of a frame in progress before
+/// reporting that it's busy, in timer ticks. Waking the panel or sending it a chunk normally takes
+/// far less.
+const IDLE_LIMIT: u64 = 2_000_000;
+
+/// Set by [`Busy`] once the panel has stayed busy too long, and cleared when the next frame
+/// begins. Only used from core 1.
+static BUSY_TIMED_OUT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Copy, Clone)]
+pub enum Error {
+    BusyTimeout,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::BusyTimeout => {
+                write!(f, "panel stayed busy for over {}s", BUSY_LIMIT / 1_000_000)
+            }
+        }
+    }
+}
+
+impl From<crate::error::Infallible> for Error {
+    fn from(err: crate::error::Infallible) -> Self {
+        match err {}
+    }
+}
+
+/// The panel's BUSY pin, which is low while the panel is busy. The driver waits on it without any
+/// limit, so once the panel has been busy for too long this reports it as idle until the next
+/// frame begins and sets [`BUSY_TIMED_OUT`].
+struct Busy {
+    pin: EpdBusy,
+    timer: Timer,
+    /// When the pin was first seen busy, since it was last seen idle.
+    since: Option<u64>,
+}
+
+impl ErrorType for Busy {
+    type Error = core::convert::Infallible;
+}
+
+impl InputPin for Busy {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.is_low()?)
+    }
+
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        if BUSY_TIMED_OUT.load(Ordering::Relaxed) || !self.pin.is_low()? {
+            self.since = None;
+            return Ok(false);
+        }
+        let now = self.timer.get_counter().ticks();
+        if now - *self.since.get_or_insert(now) > BUSY_LIMIT {
+            BUSY_TIMED_OUT.store(true, Ordering::Relaxed);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+}
+
+/// The panel itself, driven from core 1 so that core 0 can keep servicing USB during the ~30
+/// seconds a refresh takes.
+pub struct Panel {
     spi: Spi,
     device: Device,
-    display: Display7in3f,
 }
 
-impl Display {
+impl Panel {
     pub fn new(
         mut spi: Spi,
         epd_busy: EpdBusy,
@@ -38,38 +136,365 @@ impl Display {
         timer: &mut Timer,
     ) -> Result<Self, crate::error::Infallible> {
         Ok(Self {
-            device: Epd7in3f::new(&mut spi, epd_busy, epd_dc, epd_reset, timer, None)?,
-            display: Display7in3f::default(),
+            device: Epd7in3f::new(
+                &mut spi,
+                Busy {
+                    pin: epd_busy,
+                    timer: *timer,
+                    since: None,
+                },
+                epd_dc,
+                epd_reset,
+                timer,
+                None,
+            )?,
             spi,
         })
     }
 
+    /// Wake the panel and start sending it a frame, the frame's data follows with [`Self::data`].
+    fn begin(&mut self, timer: &mut Timer) -> Result<(), crate::error::Infallible> {
+        // Resetting the panel while waking it gives it another chance after timing out
+        BUSY_TIMED_OUT.store(false, Ordering::Relaxed);
+        self.device.wake_up(&mut self.spi, timer)?;
+
+        // Start the transmission without any data, the driver leaves the DC pin selecting data so
+        // the frame can be sent in pieces afterwards
+        self.device.update_frame(&mut self.spi, &[], timer)?;
+
+        Ok(())
+    }
+
+    /// Send the next part of the frame's nibble-packed data to the panel's RAM.
+    fn data(&mut self, data: &[u8]) -> Result<(), crate::error::Infallible> {
+        self.spi.write(data)?;
+        Ok(())
+    }
+
+    /// Show the frame the panel has received, then put it back to sleep.
+    fn show(&mut self, timer: &mut Timer) -> Result<(), crate::error::Infallible> {
+        self.device.display_frame(&mut self.spi, timer)?;
+
+        self.device.sleep(&mut self.spi, timer)?;
+
+        Ok(())
+    }
+
+    /// Core 1's main loop, running each operation core 0 sends.
+    fn run(mut self, mut timer: Timer) -> ! {
+        // SAFETY: core 1 only uses its own side of the FIFO
+        let pac = unsafe { pac::Peripherals::steal() };
+        let mut fifo = Sio::new(pac.SIO).fifo;
+
+        let mut reply = DONE;
+        loop {
+            let result = match idle_ram(reply) {
+                BEGIN => self.begin(&mut timer),
+                DATA => {
+                    let ptr = fifo.read_blocking() as *const u8;
+                    let len = fifo.read_blocking() as usize;
+                    // SAFETY: core 0 leaves the data alone until we reply
+                    self.data(unsafe { core::slice::from_raw_parts(ptr, len) })
+                }
+                SHOW => self.show(&mut timer),
+                op => panic!("unknown panel operation {op}"),
+            };
+            reply = match result.map_err(Error::from).and_then(|()| busy_result()) {
+                Ok(()) => DONE,
+                Err(Error::BusyTimeout) => BUSY_TIMEOUT,
+            };
+        }
+    }
+}
+
+/// Fail if the panel has timed out since the frame began, [`Busy`] doesn't wait for it again
+/// until the next frame so the rest of the frame's operations fail quickly too.
+fn busy_result() -> Result<(), Error> {
+    if BUSY_TIMED_OUT.load(Ordering::Relaxed) {
+        Err(Error::BusyTimeout)
+    } else {
+        Ok(())
+    }
+}
+
+/// Send `reply` to core 0 then wait for the next word from it, running from RAM and touching only
+/// the SIO so that core 0 can write to the flash meanwhile.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+fn idle_ram(reply: u32) -> u32 {
+    const FIFO_ST: *const u32 = 0xd000_0050 as *const u32;
+    const FIFO_WR: *mut u32 = 0xd000_0054 as *mut u32;
+    const FIFO_RD: *const u32 = 0xd000_0058 as *const u32;
+    const FIFO_ST_VLD: u32 = 1 << 0;
+    const FIFO_ST_RDY: u32 = 1 << 1;
+
+    // SAFETY: the FIFO registers are only used by core 1 from this side
+    unsafe {
+        while FIFO_ST.read_volatile() & FIFO_ST_RDY == 0 {}
+        FIFO_WR.write_volatile(reply);
+        while FIFO_ST.read_volatile() & FIFO_ST_VLD == 0 {}
+        FIFO_RD.read_volatile()
+    }
+}
+
+pub struct Display {
+    #[cfg(not(feature = "streaming"))]
+    display: Display7in3f,
+    #[cfg(feature = "streaming")]
+    checksum: Crc32,
+    /// A stored frame to send to the panel when it's shown.
+    #[cfg(feature = "streaming")]
+    loaded: Option<&'static [u8]>,
+    /// The data of the last chunk sent to core 1, which it reads until it replies.
+    #[cfg(feature = "streaming")]
+    data: Option<OctBytes>,
+    fifo: SioFifo,
+    timer: Timer,
+    /// Operations sent to core 1 that it hasn't finished yet.
+    pending: u8,
+    /// Whether the operations pending are showing a frame, rather than sending one.
+    showing: bool,
+    /// The first operation to fail since the last refresh finished.
+    failed: Option<Error>,
+    /// How many refreshes have been started, wrapping around.
+    refreshes: u16,
+}
+
+impl Display {
+    /// Start core 1 driving `panel`, and wait until it's ready.
+    pub fn new(
+        panel: Panel,
+        timer: Timer,
+        mut fifo: SioFifo,
+        psm: &mut pac::PSM,
+        ppb: &mut pac::PPB,
+    ) -> Self {
+        let mut multicore = Multicore::new(psm, ppb, &mut fifo);
+        // SAFETY: the stack is only ever given to core 1, once
+        let stack = unsafe { &mut *core::ptr::addr_of_mut!(CORE1_STACK.mem) };
+        multicore.cores()[1]
+            .spawn(stack, move || panel.run(timer))
+            .unwrap();
+        assert_eq!(fifo.read_blocking(), DONE);
+
+        Self {
+            #[cfg(not(feature = "streaming"))]
+            display: Display7in3f::default(),
+            #[cfg(feature = "streaming")]
+            checksum: Crc32::new(),
+            #[cfg(feature = "streaming")]
+            loaded: None,
+            #[cfg(feature = "streaming")]
+            data: None,
+            fifo,
+            timer,
+            pending: 0,
+            showing: false,
+            failed: None,
+            refreshes: 0,
+        }
+    }
+
+    /// Whether core 1 is refreshing the panel, the frame must not change until it's done. The
+    /// flash must not be written until [`Self::wait_idle`] too, as core 1 may be sending a frame.
+    pub fn refreshing(&self) -> bool {
+        self.showing
+    }
+
+    /// Wait a short while for core 1 to finish the operations sent for the frame in progress,
+    /// feeding `watchdog` meanwhile, returning whether it has. A refresh takes far too long to wait
+    /// for, so this returns `false` straight away while refreshing.
+    pub fn wait_idle(&mut self, watchdog: &mut Watchdog) -> bool {
+        if self.refreshing() {
+            return false;
+        }
+        let start = self.timer.get_counter().ticks();
+        while self.pending > 0 {
+            match self.fifo.read() {
+                Some(reply) => self.reply(reply),
+                None if self.timer.get_counter().ticks() - start > IDLE_LIMIT => return false,
+                None => watchdog.feed(),
+            }
+        }
+        true
+    }
+
+    /// The number of the last refresh started by [`Self::show`], for the host to tell which
+    /// refresh an event is about.
+    pub fn refreshes(&self) -> u16 {
+        self.refreshes
+    }
+
+    /// The result of a refresh started by [`Self::show`], once it has finished.
+    pub fn refreshed(&mut self, activity: &mut LedActivity) -> Option<Result<(), Error>> {
+        // Collects the replies to a frame's operations in streaming builds too
+        while self.pending > 0 {
+            let reply = self.fifo.read()?;
+            self.reply(reply);
+        }
+        if !self.showing {
+            return None;
+        }
+        self.showing = false;
+        Some(self.finish(activity))
+    }
+
+    /// Finish a refresh once core 1 has replied to all its operations.
+    fn finish(&mut self, activity: &mut LedActivity) -> Result<(), Error> {
+        activity.set_low().map_err(crate::error::Infallible::from)?;
+        self.failed.take().map_or(Ok(()), Err)
+    }
+
+    /// Record core 1's reply to an operation, keeping the first failure.
+    fn reply(&mut self, reply: u32) {
+        self.pending -= 1;
+        if reply == BUSY_TIMEOUT {
+            self.failed.get_or_insert(Error::BusyTimeout);
+        }
+    }
+
+    /// Send core 1 an operation, without waiting for it to finish.
+    fn send(&mut self, op: &[u32]) {
+        for &word in op {
+            self.fifo.write_blocking(word);
+        }
+        self.pending += 1;
+    }
+
+    /// Have core 1 send the `len` bytes at `data` to the panel then show them, check
+    /// [`Self::refreshed`] for when it's done.
+    fn refresh(&mut self, data: *const u8, len: usize) {
+        self.send(&[BEGIN]);
+        self.send(&[DATA, data as u32, len as u32]);
+        self.send(&[SHOW]);
+    }
+}
+
+#[cfg(not(feature = "streaming"))]
+impl Display {
     pub fn clear(&mut self) {
+        assert!(!self.refreshing());
         self.display.clear(OctColor::White).unwrap();
     }
 
-    pub fn update(&mut self, chunk: Chunk) {
-        self.display.draw_iter(chunk.oct_pixels()).unwrap();
+    pub fn draw(&mut self, pixels: impl IntoIterator<Item = Pixel<OctColor>>) {
+        assert!(!self.refreshing());
+        self.display.draw_iter(pixels).unwrap();
     }
 
-    pub fn show(
-        &mut self,
-        timer: &mut Timer,
-        activity: &mut LedActivity,
-    ) -> Result<(), crate::error::Infallible> {
+    /// Draw `chunk`, which must already have been checked against the palette.
+    pub fn draw_chunk(&mut self, chunk: &Chunk) {
+        assert!(!self.refreshing());
+        chunk
+            .write_oct_buffer(GEOMETRY, self.display.get_mut_buffer())
+            .unwrap();
+    }
+
+    /// The raw framebuffer, for saving to flash.
+    pub fn buffer(&self) -> &[u8] {
+        self.display.buffer()
+    }
+
+    /// Replace the framebuffer with one previously returned by [`Self::buffer`].
+    pub fn load(&mut self, buffer: &[u8]) {
+        assert!(!self.refreshing());
+        self.display.get_mut_buffer().copy_from_slice(buffer);
+    }
+
+    /// Checksum of the current framebuffer contents, for comparison with the checksum the host
+    /// calculated for the frame it sent.
+    pub fn checksum(&self) -> u32 {
+        frame_checksum((0..GEOMETRY.chunks()).map(|counter| self.chunk(counter)))
+    }
+
+    /// Whether the framebuffer holds the frame with `checksum`, so a delta can be drawn over it.
+    pub fn holds(&self, checksum: u32) -> bool {
+        self.checksum() == checksum
+    }
+
+    /// The chunk with `counter` of the current framebuffer contents.
+    pub fn chunk(&self, counter: u16) -> Chunk {
+        Chunk::from_oct_buffer(GEOMETRY, counter, self.display.buffer())
+    }
+
+    /// Have core 1 show the framebuffer on the panel, check [`Self::refreshed`] for when it's
+    /// done.
+    pub fn show(&mut self, activity: &mut LedActivity) -> Result<(), crate::error::Infallible> {
+        assert!(!self.refreshing());
+        self.refreshes = self.refreshes.wrapping_add(1);
+        self.showing = true;
         activity.set_high()?;
 
-        self.device.wake_up(&mut self.spi, timer)?;
+        // The framebuffer doesn't change until the refresh has finished
+        let buffer = self.display.buffer();
+        self.refresh(buffer.as_ptr(), buffer.len());
 
-        // Display updated frame
-        self.device
-            .update_frame(&mut self.spi, &self.display.buffer(), timer)?;
+        Ok(())
+    }
+}
 
-        self.device.display_frame(&mut self.spi, timer)?;
+/// Without a framebuffer, each chunk is converted and written to the panel's own RAM as it's
+/// drawn, so chunks must be drawn in order.
+#[cfg(feature = "streaming")]
+impl Display {
+    /// Start a new frame on the panel.
+    pub fn clear(&mut self) {
+        assert!(!self.refreshing());
+        self.checksum = Crc32::new();
+        self.loaded = None;
+        self.failed = None;
+        self.send(&[BEGIN]);
+    }
 
-        self.device.sleep(&mut self.spi, timer)?;
+    /// Send `chunk` to the panel, it must already have been checked against the palette and be
+    /// the next chunk of the frame.
+    pub fn draw_chunk(&mut self, chunk: &Chunk) {
+        assert!(!self.refreshing());
+        self.checksum.update(chunk.data());
+
+        // Only the last chunk's data is kept, so wait for core 1 to finish with it. The command
+        // was only handled once core 1 had woken the panel, so this is just the time it takes to
+        // send a chunk over SPI.
+        while self.pending > 0 {
+            let reply = self.fifo.read_blocking();
+            self.reply(reply);
+        }
+
+        // Chunks are laid out the same as the panel's RAM, so they can be sent one after another
+        let bytes = self.data.insert(chunk.oct_bytes(GEOMETRY).unwrap());
+        let (ptr, len) = (bytes.as_ptr() as u32, bytes.len() as u32);
+        self.send(&[DATA, ptr, len]);
+    }
+
+    /// Show `buffer`, a whole frame of nibble-packed data stored in flash, next time the panel is
+    /// shown.
+    pub fn load(&mut self, buffer: &'static [u8]) {
+        assert!(!self.refreshing());
+        self.loaded = Some(buffer);
+    }
+
+    /// Checksum of the chunks drawn since the frame started, for comparison with the checksum the
+    /// host calculated for the frame it sent.
+    pub fn checksum(&self) -> u32 {
+        self.checksum.finish()
+    }
+
+    /// There's no framebuffer to draw a delta over.
+    pub fn holds(&self, _checksum: u32) -> bool {
+        false
+    }
+
+    /// Have core 1 show the frame on the panel, check [`Self::refreshed`] for when it's done.
+    pub fn show(&mut self, activity: &mut LedActivity) -> Result<(), crate::error::Infallible> {
+        assert!(!self.refreshing());
+        self.refreshes = self.refreshes.wrapping_add(1);
+        self.showing = true;
+        activity.set_high()?;
 
-        activity.set_low()?;
+        match self.loaded.take() {
+            Some(buffer) => self.refresh(buffer.as_ptr(), buffer.len()),
+            None => self.send(&[SHOW]),
+        }
 
         Ok(())
     }
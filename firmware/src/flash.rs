@@ -0,0 +1,255 @@
+//! Frames saved to the flash after the firmware, so that they can be shown again without a host
+//! attached.
+//!
+//! Each slot starts with a sector holding a header, followed by the framebuffer. The header is
+//! written last, so a slot interrupted while saving reads as empty. The slideshow config is kept
+//! in the sector before the slots.
+
+use waveshare_rp2040_epaper_73::hal::{rom_data, watchdog::Watchdog};
+use zerocopy::{IntoBytes, TryFromBytes};
+use ἐννεάς_protocol::slots::{SlideshowConfig, SlotInfo};
+
+use crate::GEOMETRY;
+
+const XIP_BASE: usize = 0x1000_0000;
+const FLASH_BYTES: usize = 2048 * 1024;
+const SECTOR_BYTES: usize = 4096;
+const PAGE_BYTES: usize = 256;
+const BLOCK_BYTES: usize = 64 * 1024;
+
+/// Where the slideshow config is stored, the firmware must fit before this, see `memory.x`.
+const CONFIG_START: usize = 508 * 1024;
+
+const SLOTS_START: usize = 512 * 1024;
+
+/// The framebuffer holds a nibble per pixel.
+const FRAME_BYTES: usize = GEOMETRY.width() as usize * GEOMETRY.height() as usize / 2;
+
+const SLOT_BYTES: usize = SECTOR_BYTES + FRAME_BYTES.next_multiple_of(SECTOR_BYTES);
+
+pub const SLOTS: u8 = ((FLASH_BYTES - SLOTS_START) / SLOT_BYTES) as u8;
+
+const MAGIC: [u8; 4] = *b"enns";
+const CONFIG_MAGIC: [u8; 4] = *b"ennc";
+
+const _: () = assert!(FRAME_BYTES % PAGE_BYTES == 0);
+const _: () = assert!(SLOTS > 0);
+
+fn offset(slot: u8) -> usize {
+    assert!(slot < SLOTS);
+    SLOTS_START + usize::from(slot) * SLOT_BYTES
+}
+
+fn read(offset: usize, len: usize) -> &'static [u8] {
+    // SAFETY: the flash is mapped for reading at `XIP_BASE`, and only changes through `write`
+    // which needs exclusive access to the slot
+    unsafe { core::slice::from_raw_parts((XIP_BASE + offset) as *const u8, len) }
+}
+
+pub fn info(slot: u8) -> SlotInfo {
+    let header = read(offset(slot), 8);
+    if header[..4] == MAGIC {
+        SlotInfo::new(u32::from_le_bytes(header[4..].try_into().unwrap()))
+    } else {
+        SlotInfo::EMPTY
+    }
+}
+
+/// The framebuffer stored in `slot`, if there is one.
+pub fn frame(slot: u8) -> Option<&'static [u8]> {
+    info(slot).checksum()?;
+    Some(read(offset(slot) + SECTOR_BYTES, FRAME_BYTES))
+}
+
+/// Store `frame` in `slot`, identified by its `checksum`.
+#[cfg(not(feature = "streaming"))]
+pub fn save(slot: u8, frame: &[u8], checksum: u32, watchdog: &mut Watchdog) {
+    assert!(frame.len() == FRAME_BYTES);
+
+    let mut header = [0xff; PAGE_BYTES];
+    header[..4].copy_from_slice(&MAGIC);
+    header[4..8].copy_from_slice(&checksum.to_le_bytes());
+
+    let offset = offset(slot);
+    write(offset, SLOT_BYTES, &[], watchdog);
+    write(offset + SECTOR_BYTES, 0, frame, watchdog);
+    write(offset, 0, &header, watchdog);
+}
+
+pub fn delete(slot: u8, watchdog: &mut Watchdog) {
+    write(offset(slot), SECTOR_BYTES, &[], watchdog);
+}
+
+/// The saved slideshow config, stopped if none has been saved.
+pub fn slideshow() -> SlideshowConfig {
+    let config = read(CONFIG_START, 4 + size_of::<SlideshowConfig>());
+    if config[..4] != CONFIG_MAGIC {
+        return SlideshowConfig::STOPPED;
+    }
+    SlideshowConfig::try_read_from_bytes(&config[4..]).unwrap_or(SlideshowConfig::STOPPED)
+}
+
+pub fn save_slideshow(config: &SlideshowConfig, watchdog: &mut Watchdog) {
+    let mut page = [0xff; PAGE_BYTES];
+    page[..4].copy_from_slice(&CONFIG_MAGIC);
+    page[4..][..size_of::<SlideshowConfig>()].copy_from_slice(config.as_bytes());
+    write(CONFIG_START, SECTOR_BYTES, &page, watchdog);
+}
+
+/// The boot ROM functions for changing the flash, looked up before leaving XIP mode since the
+/// lookup runs from flash.
+struct Rom {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+}
+
+impl Rom {
+    fn get() -> Self {
+        Self {
+            connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+            flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+            flash_range_erase: rom_data::flash_range_erase::ptr(),
+            flash_range_program: rom_data::flash_range_program::ptr(),
+            flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        }
+    }
+}
+
+/// A copy of the second stage bootloader, which sets up fast XIP reads, to restore them after
+/// leaving XIP mode.
+fn boot2() -> [u32; 64] {
+    let mut boot2 = [0u32; 64];
+    // SAFETY: the second stage bootloader is the first 256 bytes of flash
+    unsafe { core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), 64) };
+    boot2
+}
+
+/// The flash chip's 64-bit unique id, read the same way as the Pico SDK.
+pub fn unique_id() -> [u8; 8] {
+    // Read unique id, 4 dummy bytes, then the id
+    let mut tx = [0; 13];
+    tx[0] = 0x4b;
+    let mut rx = [0; 13];
+
+    let (rom, boot2) = (Rom::get(), boot2());
+    // SAFETY: nothing else runs from flash while interrupts are disabled, this is read before
+    // core 1 is started
+    cortex_m::interrupt::free(|_| unsafe {
+        command_ram(&rom, boot2.as_ptr(), tx.as_ptr(), rx.as_mut_ptr(), tx.len())
+    });
+
+    rx[5..].try_into().unwrap()
+}
+
+/// Erase `erase` bytes at `offset` into the flash, then program `data` at the same offset.
+///
+/// This is done a 64k block at a time, feeding the watchdog in between, since at worst erasing and
+/// programming a whole frame takes longer than the watchdog allows.
+fn write(offset: usize, erase: usize, data: &[u8], watchdog: &mut Watchdog) {
+    assert!(offset >= CONFIG_START && offset + erase.max(data.len()) <= FLASH_BYTES);
+    assert!(offset % SECTOR_BYTES == 0 && erase % SECTOR_BYTES == 0);
+    assert!(data.len() % PAGE_BYTES == 0);
+
+    let (rom, boot2) = (Rom::get(), boot2());
+
+    for start in (0..erase.max(data.len())).step_by(BLOCK_BYTES) {
+        let erase = erase.saturating_sub(start).min(BLOCK_BYTES);
+        let data = data.get(start..).unwrap_or(&[]);
+        let data = &data[..data.len().min(BLOCK_BYTES)];
+
+        watchdog.feed();
+        // SAFETY: nothing else runs from flash while interrupts are disabled, core 1 waits from
+        // RAM when it isn't refreshing the panel, and the flash isn't written while it is
+        cortex_m::interrupt::free(|_| unsafe {
+            write_ram(
+                &rom,
+                boot2.as_ptr(),
+                (offset + start) as u32,
+                erase,
+                data.as_ptr(),
+                data.len(),
+            )
+        });
+    }
+    watchdog.feed();
+}
+
+/// Runs from RAM since the flash can't be read while it's being changed, so this must not call
+/// anything outside of RAM or the boot ROM.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn write_ram(
+    rom: &Rom,
+    boot2: *const u32,
+    offset: u32,
+    erase: usize,
+    data: *const u8,
+    len: usize,
+) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    if erase > 0 {
+        // Use 64k block erases where possible, the ROM falls back to sectors otherwise
+        (rom.flash_range_erase)(offset, erase, 1 << 16, 0xd8);
+    }
+    if len > 0 {
+        (rom.flash_range_program)(offset, data, len);
+    }
+    (rom.flash_flush_cache)();
+
+    enter_xip(boot2);
+}
+
+const SSI_SR: *const u32 = 0x1800_0028 as *const u32;
+const SSI_DR0: *mut u32 = 0x1800_0060 as *mut u32;
+const SSI_SR_TFNF: u32 = 1 << 1;
+const SSI_SR_RFNE: u32 = 1 << 3;
+const QSPI_SS_CTRL: *mut u32 = 0x4001_800c as *mut u32;
+const QSPI_SS_CTRL_OUTOVER: u32 = 0b11 << 8;
+const QSPI_SS_CTRL_OUTOVER_LOW: u32 = 0b10 << 8;
+const QSPI_SS_CTRL_OUTOVER_HIGH: u32 = 0b11 << 8;
+
+/// Send the `len` bytes at `tx` to the flash chip as a single command, storing the bytes clocked
+/// out at the same time at `rx`.
+///
+/// Runs from RAM like [`write_ram`], only touching the SSI and QSPI pad registers directly.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn command_ram(rom: &Rom, boot2: *const u32, tx: *const u8, rx: *mut u8, len: usize) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+
+    // Force chip select low for the whole command
+    let ctrl = QSPI_SS_CTRL.read_volatile() & !QSPI_SS_CTRL_OUTOVER;
+    QSPI_SS_CTRL.write_volatile(ctrl | QSPI_SS_CTRL_OUTOVER_LOW);
+
+    let (mut sent, mut received) = (0, 0);
+    while received < len {
+        let status = SSI_SR.read_volatile();
+        // Keep the FIFOs from overflowing, they hold 16 entries
+        if status & SSI_SR_TFNF != 0 && sent < len && sent - received < 14 {
+            SSI_DR0.write_volatile(u32::from(*tx.add(sent)));
+            sent += 1;
+        }
+        if status & SSI_SR_RFNE != 0 {
+            *rx.add(received) = SSI_DR0.read_volatile() as u8;
+            received += 1;
+        }
+    }
+
+    QSPI_SS_CTRL.write_volatile(ctrl | QSPI_SS_CTRL_OUTOVER_HIGH);
+
+    (rom.flash_flush_cache)();
+    enter_xip(boot2);
+}
+
+/// Run the copy of the second stage bootloader, inlined so it stays in RAM with the caller.
+#[inline(always)]
+unsafe fn enter_xip(boot2: *const u32) {
+    // Thumb code, so the low bit of the address must be set
+    let enter_xip: unsafe extern "C" fn() = core::mem::transmute(boot2 as usize + 1);
+    enter_xip();
+}
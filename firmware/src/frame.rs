@@ -0,0 +1,246 @@
+#[cfg(not(feature = "streaming"))]
+use ἐννεάς_protocol::RegionChunk;
+use ἐννεάς_protocol::{FrameStatus, InvalidPixel, MissingChunks};
+
+use crate::GEOMETRY;
+
+const CHUNKS: u16 = GEOMETRY.chunks();
+const WORDS: usize = (CHUNKS as usize).div_ceil(32);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Idle,
+    /// A whole frame, started by `Command::Start`, every chunk must be received.
+    Full,
+    /// A whole frame, started by `Command::Start` keeping the current frame, only the changed
+    /// chunks are received.
+    Delta,
+    /// An update to part of the frame, started by the first `Command::Region`.
+    #[cfg(not(feature = "streaming"))]
+    Partial,
+}
+
+/// Tracks which chunks of the current frame have been received.
+pub struct Frame {
+    state: State,
+    /// Whether a frame was discarded part way through since the last one finished, leaving the
+    /// framebuffer out of sync with the panel.
+    discarded: bool,
+    received: [u32; WORDS],
+    duplicates: u16,
+    out_of_order: u16,
+    last: Option<u16>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Error {
+    NotStarted,
+    CounterOutOfRange(u16),
+    #[cfg(not(feature = "streaming"))]
+    RegionOutOfBounds {
+        x: u16,
+        y: u16,
+        len: u16,
+    },
+    InvalidCompressed(u16),
+    InvalidPixel(InvalidPixel),
+    #[cfg(not(feature = "streaming"))]
+    Discarded,
+    #[cfg(not(feature = "streaming"))]
+    InProgress,
+    ChecksumRequired,
+    Incomplete(MissingChunks),
+    ChecksumMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    #[cfg(feature = "streaming")]
+    OutOfOrder {
+        expected: u16,
+        counter: u16,
+    },
+}
+
+impl Frame {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Idle,
+            discarded: false,
+            received: [0; WORDS],
+            duplicates: 0,
+            out_of_order: 0,
+            last: None,
+        }
+    }
+
+    /// Start a whole frame, if `keep` is set only the chunks that differ from the current frame
+    /// will be received.
+    pub fn start(&mut self, keep: bool) {
+        *self = Self {
+            discarded: self.discarded,
+            ..Self::new()
+        };
+        self.state = if keep { State::Delta } else { State::Full };
+    }
+
+    pub fn in_progress(&self) -> bool {
+        self.state != State::Idle
+    }
+
+    /// Discard the frame in progress, whatever was already drawn stays in the framebuffer.
+    pub fn abort(&mut self) {
+        if self.in_progress() {
+            self.discarded = true;
+        }
+        self.state = State::Idle;
+    }
+
+    /// Record that the chunk with `counter` was received, retransmitted chunks are accepted and
+    /// replace the earlier data. When streaming chunks must arrive in order instead, since they
+    /// go straight to the panel.
+    pub fn receive(&mut self, counter: u16) -> Result<(), Error> {
+        if self.state == State::Idle {
+            return Err(Error::NotStarted);
+        }
+        if counter >= CHUNKS {
+            return Err(Error::CounterOutOfRange(counter));
+        }
+
+        #[cfg(feature = "streaming")]
+        {
+            let expected = self.last.map_or(0, |last| last + 1);
+            if counter != expected {
+                return Err(Error::OutOfOrder { expected, counter });
+            }
+        }
+
+        #[cfg(not(feature = "streaming"))]
+        if self.is_received(counter) {
+            self.duplicates += 1;
+        } else if self.last.is_some_and(|last| counter != last + 1) {
+            self.out_of_order += 1;
+        }
+
+        self.received[usize::from(counter / 32)] |= 1 << (counter % 32);
+        self.last = Some(counter);
+
+        Ok(())
+    }
+
+    /// Record that a region was received, starting a partial update if no frame is in progress.
+    #[cfg(not(feature = "streaming"))]
+    pub fn region(&mut self, region: &RegionChunk) -> Result<(), Error> {
+        let (x, y) = region.origin();
+        let len = region.len() as u16;
+        if u32::from(x) + u32::from(len) > u32::from(GEOMETRY.width()) || y >= GEOMETRY.height() {
+            return Err(Error::RegionOutOfBounds { x, y, len });
+        }
+
+        if self.state == State::Idle {
+            // The framebuffer may hold part of the discarded frame, which this would show
+            if self.discarded {
+                return Err(Error::Discarded);
+            }
+            self.state = State::Partial;
+        }
+
+        Ok(())
+    }
+
+    /// Check that every chunk of a full frame has been received, if not the frame stays in
+    /// progress so that the missing chunks can be retransmitted.
+    pub fn check(&self) -> Result<(), Error> {
+        match self.state {
+            State::Idle => return Err(Error::NotStarted),
+            #[cfg(not(feature = "streaming"))]
+            State::Partial => return Ok(()),
+            State::Delta => return Ok(()),
+            State::Full => {}
+        }
+
+        let missing = CHUNKS - self.received();
+        if missing > 0 {
+            return Err(Error::Incomplete(MissingChunks::new(
+                missing,
+                self.missing(),
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Check that the frame's checksum can be skipped, which only a partial update may do since
+    /// the host doesn't know the rest of the framebuffer to include it.
+    pub fn check_skip(&self) -> Result<(), Error> {
+        match self.state {
+            #[cfg(not(feature = "streaming"))]
+            State::Partial => Ok(()),
+            _ => Err(Error::ChecksumRequired),
+        }
+    }
+
+    /// Check that the framebuffer holds the frame last shown, rather than part of one still being
+    /// received or one that was discarded.
+    #[cfg(not(feature = "streaming"))]
+    pub fn check_shown(&self) -> Result<(), Error> {
+        if self.in_progress() {
+            Err(Error::InProgress)
+        } else if self.discarded {
+            Err(Error::Discarded)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.state = State::Idle;
+        self.discarded = false;
+    }
+
+    /// The status of the frame in progress, along with how many `refreshes` the display has
+    /// started.
+    pub fn status(&self, refreshes: u16) -> FrameStatus {
+        let in_progress = matches!(self.state, State::Full | State::Delta);
+        FrameStatus::new(in_progress, self.contiguous(), self.received(), refreshes)
+    }
+
+    pub fn received(&self) -> u16 {
+        self.received
+            .iter()
+            .map(|word| word.count_ones() as u16)
+            .sum()
+    }
+
+    pub fn duplicates(&self) -> u16 {
+        self.duplicates
+    }
+
+    pub fn out_of_order(&self) -> u16 {
+        self.out_of_order
+    }
+
+    /// How many chunks from the start of the frame have all been received.
+    fn contiguous(&self) -> u16 {
+        (0..CHUNKS)
+            .find(|&counter| !self.is_received(counter))
+            .unwrap_or(CHUNKS)
+    }
+
+    fn is_received(&self, counter: u16) -> bool {
+        self.received[usize::from(counter / 32)] & (1 << (counter % 32)) != 0
+    }
+
+    fn missing(&self) -> impl Iterator<Item = core::ops::Range<u16>> + '_ {
+        let mut counter = 0;
+        core::iter::from_fn(move || {
+            while counter < CHUNKS && self.is_received(counter) {
+                counter += 1;
+            }
+            let start = counter;
+            while counter < CHUNKS && !self.is_received(counter) {
+                counter += 1;
+            }
+            (start < counter).then_some(start..counter)
+        })
+    }
+}
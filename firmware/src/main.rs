@@ -3,30 +3,58 @@
 
 extern crate ennead_protocol as ἐννεάς_protocol;
 
+use core::fmt::Write;
 use embedded_hal::digital::OutputPin;
 use embedded_hal_bus::spi::ExclusiveDevice;
 use heapless::String;
-use panic_halt as _;
 use usb_device::bus::UsbBusAllocator;
-use ἐννεάς_protocol::{Command, Response};
+use ἐννεάς_protocol::{
+    error::DeviceError,
+    event::{Event, LastReset, ResetReason},
+    geometry::Geometry,
+    info::{DeviceInfo, Features, Panel},
+    panic::LastPanic,
+    slots::SlotList,
+    Command, Response, SmolStr,
+};
 
-use fugit::RateExtU32;
+use fugit::{MicrosDurationU32, RateExtU32};
 use waveshare_rp2040_epaper_73::{
     hal::{
-        clocks::init_clocks_and_plls, pac, timer::Timer, usb::UsbBus, watchdog::Watchdog, Clock,
-        Sio, Spi,
+        clocks::init_clocks_and_plls, pac, rom_data, timer::Timer, usb::UsbBus, watchdog::Watchdog,
+        Clock, Sio, Spi,
     },
     EpdPowerEnable, LedActivity, LedPower, Pins, XOSC_CRYSTAL_FREQ,
 };
 
 mod display;
 mod error;
+mod flash;
+mod frame;
+mod panic;
+mod slideshow;
 mod usb;
 
+const PANEL: Panel = Panel::Epd7in3f;
+const GEOMETRY: Geometry = PANEL.geometry();
+
+/// How long a frame in progress can go without any commands before it's discarded, in timer
+/// ticks. This leaves time to resume the frame after the host loses the connection.
+const FRAME_TIMEOUT: u64 = 60_000_000;
+
+/// How long the main loop can go without feeding the watchdog before the device resets, close to
+/// the longest the hardware allows. Flash writes feed it between each 64k block, which at worst
+/// takes around 3 seconds.
+const WATCHDOG_PERIOD: MicrosDurationU32 = MicrosDurationU32::millis(8_000);
+
+/// How long core 1 can take over a refresh before it's assumed to be stuck, in timer ticks. A
+/// refresh normally takes around 30 seconds, and core 1 gives up on a panel stuck busy after 60.
+const REFRESH_TIMEOUT: u64 = 120_000_000;
+
 fn read_serial() -> u32 {
-    // TODO: The RP2040 doesn't have a unique id, the sdk reads the id from the flash chip, I don't
-    // know if this configuration has a flash chip or how to read it though 😔.
-    return 0xeeeeeeee;
+    // The RP2040 doesn't have a unique id, so like the sdk use the flash chip's, folded to fit
+    let id = u64::from_be_bytes(flash::unique_id());
+    (id >> 32) as u32 ^ id as u32
 }
 
 fn aegean_u16(mut target: u16, result: &mut String<64>) {
@@ -69,9 +97,141 @@ fn aegean_u32(value: u32) -> String<64> {
     result
 }
 
+fn device_info() -> DeviceInfo {
+    let features = Features::CHECKSUM
+        | Features::COMPRESSED
+        | Features::ABORT
+        | Features::RESUME
+        | Features::EVENTS
+        | Features::SLOTS
+        | Features::SLIDESHOW
+        | Features::REBOOT
+        | Features::PANIC
+        | Features::WATCHDOG;
+    #[cfg(not(feature = "streaming"))]
+    let features =
+        features | Features::RETRANSMIT | Features::REGION | Features::DELTA | Features::READBACK;
+    #[cfg(feature = "streaming")]
+    let features = features | Features::STREAMING;
+
+    DeviceInfo::new(
+        PANEL,
+        PANEL.palette(),
+        GEOMETRY,
+        features,
+        env!("CARGO_PKG_VERSION"),
+    )
+    .unwrap()
+}
+
+fn error_response(err: frame::Error) -> Response {
+    Response::Err(match err {
+        frame::Error::Incomplete(missing) => return Response::Incomplete(missing),
+        frame::Error::NotStarted => DeviceError::NotStarted { _unused: [0; 61] },
+        frame::Error::CounterOutOfRange(counter) => DeviceError::CounterOutOfRange {
+            counter: counter.into(),
+            _unused: [0; 59],
+        },
+        #[cfg(not(feature = "streaming"))]
+        frame::Error::RegionOutOfBounds { x, y, len } => DeviceError::RegionOutOfBounds {
+            x: x.into(),
+            y: y.into(),
+            len: len.into(),
+            _unused: [0; 55],
+        },
+        frame::Error::InvalidCompressed(counter) => DeviceError::InvalidCompressed {
+            counter: counter.into(),
+            _unused: [0; 59],
+        },
+        frame::Error::InvalidPixel(err) => err.into(),
+        #[cfg(not(feature = "streaming"))]
+        frame::Error::Discarded => DeviceError::Discarded { _unused: [0; 61] },
+        #[cfg(not(feature = "streaming"))]
+        frame::Error::InProgress => DeviceError::Busy { _unused: [0; 61] },
+        frame::Error::ChecksumRequired => DeviceError::InvalidCommand {
+            msg: message("only a region update can skip the checksum"),
+        },
+        frame::Error::ChecksumMismatch { expected, actual } => DeviceError::ChecksumMismatch {
+            expected: expected.into(),
+            actual: actual.into(),
+            _unused: [0; 53],
+        },
+        #[cfg(feature = "streaming")]
+        frame::Error::OutOfOrder { expected, counter } => DeviceError::OutOfOrder {
+            expected: expected.into(),
+            counter: counter.into(),
+            _unused: [0; 57],
+        },
+    })
+}
+
+fn check_slot(slot: u8) -> Result<u8, DeviceError> {
+    if slot < flash::SLOTS {
+        Ok(slot)
+    } else {
+        Err(DeviceError::InvalidSlot {
+            slot,
+            _unused: [0; 60],
+        })
+    }
+}
+
+/// Acknowledge the command that finished the framebuffer, then start showing it on the panel.
+fn refresh(usb: &mut usb::Usb, display: &mut display::Display, led_activity: &mut LedActivity) {
+    usb.send_response(Response::Ok { _unused: [0; 62] });
+    show_frame(usb, display, led_activity);
+}
+
+/// Start showing the framebuffer on the panel, the event is sent once the refresh finishes.
+fn show_frame(usb: &mut usb::Usb, display: &mut display::Display, led_activity: &mut LedActivity) {
+    if let Err(err) = display.show(led_activity) {
+        usb.send_event(Event::RefreshFailed {
+            refresh: display.refreshes().into(),
+            msg: message(err),
+        });
+    }
+}
+
+/// Whether `command` has to wait for core 1 to finish with the panel, since it reads the
+/// framebuffer and runs from flash while refreshing or sending a streamed frame.
+fn needs_panel(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Start { .. }
+            | Command::Chunk(_)
+            | Command::Compressed(_)
+            | Command::Region(_)
+            | Command::End { .. }
+            | Command::SaveSlot { .. }
+            | Command::DeleteSlot { .. }
+            | Command::ShowSlot { .. }
+            | Command::Slideshow(_)
+    )
+}
+
+fn message<const CAP: usize>(err: impl core::fmt::Display) -> SmolStr<CAP> {
+    let mut text: String<CAP> = String::new();
+    let _ = write!(&mut text, "{err}");
+    SmolStr::new(&text).unwrap_or_else(|_| SmolStr::new("error").unwrap())
+}
+
 #[cortex_m_rt::entry]
 fn main() -> ! {
     let mut pac = pac::Peripherals::take().unwrap();
+    let last_panic = panic::take();
+
+    // Panics reset through the watchdog like reboots do, so a saved panic tells them apart
+    let reason = pac.WATCHDOG.reason().read();
+    let reset_reason = if last_panic.is_some() {
+        ResetReason::Panic
+    } else if reason.timer().bit_is_set() {
+        ResetReason::Watchdog
+    } else if reason.force().bit_is_set() {
+        ResetReason::Forced
+    } else {
+        ResetReason::PowerOn
+    };
+
     let mut watchdog = Watchdog::new(pac.WATCHDOG);
     let sio = Sio::new(pac.SIO);
 
@@ -108,12 +268,19 @@ fn main() -> ! {
     ));
 
     let serial_number = aegean_u32(read_serial());
-    let mut usb = usb::Usb::new(&usb_bus, &serial_number).unwrap();
+    let mut usb = usb::Usb::new(&usb_bus, &serial_number, last_panic).unwrap();
+    usb.send_event(Event::Booted {
+        reason: reset_reason,
+        _unused: [0; 60],
+    });
+    if let Some(msg) = last_panic {
+        usb.send_event(Event::Panicked { msg });
+    }
 
     let mut epd_power_enable: EpdPowerEnable = pins.epd_power_enable.reconfigure();
     epd_power_enable.set_high().unwrap();
 
-    let mut display = display::Display::new(
+    let panel = display::Panel::new(
         ExclusiveDevice::new_no_delay(
             Spi::new(
                 pac.SPI1,
@@ -137,25 +304,274 @@ fn main() -> ! {
         &mut timer,
     )
     .unwrap();
+    let mut display = display::Display::new(panel, timer, sio.fifo, &mut pac.PSM, &mut pac.PPB);
 
     led_power.set_high().unwrap();
 
+    let mut frame = frame::Frame::new();
+    let mut last_command = timer.get_counter().ticks();
+    let mut slideshow = slideshow::Slideshow::new(flash::slideshow(), timer.get_counter().ticks());
+    let mut refresh_started = timer.get_counter().ticks();
+
+    watchdog.pause_on_debug(true);
+    watchdog.start(WATCHDOG_PERIOD);
+
     loop {
+        // Core 1 gives up on the panel's BUSY pin by itself, so a refresh that still doesn't
+        // finish means core 1 is stuck. Panic rather than let the watchdog reset the device, so
+        // the reset is reported as a panic with this message.
+        let now = timer.get_counter().ticks();
+        if !display.refreshing() {
+            refresh_started = now;
+        }
+        if now - refresh_started > REFRESH_TIMEOUT {
+            panic!("refresh timed out");
+        }
+        watchdog.feed();
+
+        match display.refreshed(&mut led_activity) {
+            Some(Ok(())) => usb.send_event(Event::Refreshed {
+                refresh: display.refreshes().into(),
+                _unused: [0; 59],
+            }),
+            Some(Err(err)) => usb.send_event(Event::RefreshFailed {
+                refresh: display.refreshes().into(),
+                msg: message(err),
+            }),
+            None => {}
+        }
+
         let Some(command) = usb.poll(&mut timer, &mut led_activity).unwrap() else {
+            if frame.in_progress() && timer.get_counter().ticks() - last_command > FRAME_TIMEOUT {
+                usb.log(format_args!("Discarding stale frame"));
+                frame.abort();
+                usb.send_event(Event::FrameDiscarded { _unused: [0; 61] });
+            }
+            if !frame.in_progress() && !display.refreshing() {
+                let stored = slideshow
+                    .next(timer.get_counter().ticks())
+                    .and_then(|slot| Some((slot, flash::frame(slot)?)));
+                if let Some((slot, stored)) = stored {
+                    usb.log(format_args!("Slideshow showing slot {slot}"));
+                    display.load(stored);
+                    frame.finish();
+                    show_frame(&mut usb, &mut display, &mut led_activity);
+                }
+            }
             continue;
         };
+        last_command = timer.get_counter().ticks();
 
         match command {
-            Ok(command) => {
-                match command {
-                    Command::Start { .. } => display.clear(),
-                    Command::Chunk(chunk) => display.update(chunk),
-                    Command::End { .. } => display.show(&mut timer, &mut led_activity).unwrap(),
-                }
-                // usb.send_response(Response::Ok { _unused: [0; 62] });
+            Ok(command) if needs_panel(&command) && !display.wait_idle(&mut watchdog) => {
+                usb.send_response(Response::Err(DeviceError::Busy { _unused: [0; 61] }));
             }
-            Err(msg) => {
-                // usb.send_response(Response::Err { msg });
+            Ok(command) => match command {
+                Command::Start {
+                    keep,
+                    base_checksum,
+                    ..
+                } => {
+                    // If the current frame isn't the one the host expects then fall back to a
+                    // whole frame, the chunks the host didn't send will be reported as missing.
+                    let keep = keep && display.holds(base_checksum.get());
+                    usb.log(format_args!("Start, keep {keep}"));
+                    frame.start(keep);
+                    if !keep {
+                        display.clear();
+                    }
+                    usb.send_response(Response::Ok { _unused: [0; 62] });
+                }
+                Command::Chunk(chunk) => {
+                    // Check the whole chunk before recording or drawing any of it
+                    let result = chunk
+                        .check(GEOMETRY, PANEL.palette())
+                        .map_err(frame::Error::InvalidPixel)
+                        .and_then(|()| {
+                            frame.receive(chunk.counter())?;
+                            display.draw_chunk(&chunk);
+                            Ok(())
+                        });
+                    match result {
+                        Ok(()) => usb.send_response(Response::Ok { _unused: [0; 62] }),
+                        Err(err) => usb.send_response(error_response(err)),
+                    }
+                }
+                Command::Compressed(compressed) => {
+                    let result = compressed
+                        .counters()
+                        .zip(compressed.chunks(GEOMETRY))
+                        .try_for_each(|(counter, chunk)| {
+                            let chunk =
+                                chunk.map_err(|()| frame::Error::InvalidCompressed(counter))?;
+                            chunk
+                                .check(GEOMETRY, PANEL.palette())
+                                .map_err(frame::Error::InvalidPixel)?;
+                            frame.receive(counter)?;
+                            display.draw_chunk(&chunk);
+                            Ok(())
+                        });
+                    match result {
+                        Ok(()) => usb.send_response(Response::Ok { _unused: [0; 62] }),
+                        Err(err) => usb.send_response(error_response(err)),
+                    }
+                }
+                #[cfg(not(feature = "streaming"))]
+                Command::Region(region) => {
+                    // Check the bounds first, the pixels' positions are only valid within them
+                    let result = frame.region(&region).and_then(|()| {
+                        let pixels = region
+                            .oct_pixels(GEOMETRY)
+                            .map_err(frame::Error::InvalidPixel)?;
+                        display.draw(pixels);
+                        Ok(())
+                    });
+                    match result {
+                        Ok(()) => usb.send_response(Response::Ok { _unused: [0; 62] }),
+                        Err(err) => usb.send_response(error_response(err)),
+                    }
+                }
+                Command::End {
+                    checksum,
+                    skip_checksum,
+                    ..
+                } => {
+                    usb.log(format_args!(
+                        "End, received {} chunks ({} duplicate, {} out of order)",
+                        frame.received(),
+                        frame.duplicates(),
+                        frame.out_of_order(),
+                    ));
+                    let result = frame.check().and_then(|()| {
+                        if skip_checksum {
+                            return frame.check_skip();
+                        }
+                        let (expected, actual) = (checksum.get(), display.checksum());
+                        if expected == actual {
+                            Ok(())
+                        } else {
+                            Err(frame::Error::ChecksumMismatch { expected, actual })
+                        }
+                    });
+                    match result {
+                        Ok(()) => {
+                            frame.finish();
+                            slideshow.showing(None);
+                            refresh(&mut usb, &mut display, &mut led_activity);
+                        }
+                        Err(err) => usb.send_response(error_response(err)),
+                    }
+                }
+                Command::Info { .. } => usb.send_response(Response::Info(device_info())),
+                Command::Status { .. } => {
+                    usb.send_response(Response::Status(frame.status(display.refreshes())))
+                }
+                #[cfg(not(feature = "streaming"))]
+                Command::ReadBack { .. } => {
+                    usb.log(format_args!("Read back"));
+                    for counter in 0..GEOMETRY.chunks() {
+                        watchdog.feed();
+                        let response = Response::Chunk(display.chunk(counter));
+                        if !usb.send_response_waiting(response, &mut timer) {
+                            usb.log(format_args!("Host stopped reading at chunk {counter}"));
+                            break;
+                        }
+                    }
+                }
+                #[cfg(not(feature = "streaming"))]
+                Command::SaveSlot { slot, .. } => {
+                    let result = check_slot(slot).map_err(Response::Err).and_then(|slot| {
+                        frame.check_shown().map_err(error_response)?;
+                        Ok(slot)
+                    });
+                    match result {
+                        Ok(slot) => {
+                            usb.log(format_args!("Save slot {slot}"));
+                            flash::save(slot, display.buffer(), display.checksum(), &mut watchdog);
+                            usb.send_response(Response::Ok { _unused: [0; 62] });
+                        }
+                        Err(response) => usb.send_response(response),
+                    }
+                }
+                #[cfg(feature = "streaming")]
+                Command::Region(_) | Command::ReadBack { .. } | Command::SaveSlot { .. } => usb
+                    .send_response(Response::Err(DeviceError::InvalidCommand {
+                        msg: message("needs a framebuffer, which streaming builds don't have"),
+                    })),
+                Command::ListSlots { .. } => usb.send_response(Response::Slots(SlotList::new(
+                    (0..flash::SLOTS).map(flash::info),
+                ))),
+                Command::DeleteSlot { slot, .. } => match check_slot(slot) {
+                    Ok(slot) => {
+                        usb.log(format_args!("Delete slot {slot}"));
+                        flash::delete(slot, &mut watchdog);
+                        usb.send_response(Response::Ok { _unused: [0; 62] });
+                    }
+                    Err(err) => usb.send_response(Response::Err(err)),
+                },
+                Command::ShowSlot { slot, .. } => {
+                    let stored = check_slot(slot).and_then(|slot| {
+                        flash::frame(slot).ok_or(DeviceError::EmptySlot {
+                            slot,
+                            _unused: [0; 60],
+                        })
+                    });
+                    match stored {
+                        Ok(stored) => {
+                            usb.log(format_args!("Show slot {slot}"));
+                            // The stored frame replaces whatever was in progress
+                            display.load(stored);
+                            frame.finish();
+                            slideshow.showing(Some(slot));
+                            refresh(&mut usb, &mut display, &mut led_activity);
+                        }
+                        Err(err) => usb.send_response(Response::Err(err)),
+                    }
+                }
+                Command::Slideshow(config) => {
+                    match config.slots().find(|&slot| slot >= flash::SLOTS) {
+                        Some(slot) => usb.send_response(Response::Err(DeviceError::InvalidSlot {
+                            slot,
+                            _unused: [0; 60],
+                        })),
+                        None => {
+                            usb.log(format_args!(
+                                "Slideshow every {}s, running {}",
+                                config.interval(),
+                                config.is_running()
+                            ));
+                            flash::save_slideshow(&config, &mut watchdog);
+                            slideshow.configure(config, timer.get_counter().ticks());
+                            usb.send_response(Response::Ok { _unused: [0; 62] });
+                        }
+                    }
+                }
+                Command::Reboot { bootloader, .. } => {
+                    usb.log(format_args!("Rebooting, bootloader {bootloader}"));
+                    usb.send_response(Response::Ok { _unused: [0; 62] });
+                    usb.flush(&mut timer);
+                    if bootloader {
+                        // Leave both the mass storage and picoboot interfaces enabled
+                        rom_data::reset_to_usb_boot(0, 0);
+                    }
+                    panic::reset();
+                }
+                Command::LastPanic { .. } => {
+                    usb.send_response(Response::Panic(
+                        last_panic.map_or(LastPanic::NONE, LastPanic::new),
+                    ));
+                }
+                Command::ResetReason { .. } => {
+                    usb.send_response(Response::Reset(LastReset::new(reset_reason)));
+                }
+                Command::Abort { .. } => {
+                    usb.log(format_args!("Abort"));
+                    frame.abort();
+                    usb.send_response(Response::Ok { _unused: [0; 62] });
+                }
+            },
+            Err(err) => {
+                usb.send_response(Response::Err(err));
             }
         }
     }
@@ -0,0 +1,109 @@
+//! Saving panic messages in RAM that isn't initialised at startup, so they survive the watchdog
+//! reset that follows and can be reported once the device is back up. Only the first panic is
+//! kept, in case the other core panics too before the reset. Reboots reset through the watchdog
+//! the same way, the saved message is what tells a panic apart at the next boot.
+
+use core::{fmt::Write, mem::MaybeUninit, panic::PanicInfo};
+
+use waveshare_rp2040_epaper_73::hal::pac;
+use ἐννεάς_protocol::SmolStr;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"ennp");
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct Record {
+    magic: u32,
+    len: u32,
+    msg: [u8; 61],
+}
+
+#[link_section = ".uninit.ennead.panic"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+/// Appends whole characters to the message, dropping the rest once it's full.
+struct Message<'a>(&'a mut Record);
+
+impl Write for Message<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            let len = self.0.len as usize;
+            let Some(slot) = self.0.msg.get_mut(len..len + c.len_utf8()) else {
+                return Err(core::fmt::Error);
+            };
+            c.encode_utf8(slot);
+            self.0.len += c.len_utf8() as u32;
+        }
+        Ok(())
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+
+    let saved = core::ptr::addr_of_mut!(RECORD).cast::<Record>();
+    // SAFETY: reading a `u32` is fine whatever the RAM holds, `take` clears it at startup so it
+    // only matches if a panic on the other core already saved its message
+    if unsafe { core::ptr::addr_of!((*saved).magic).read_volatile() } != MAGIC {
+        save(info);
+    }
+
+    // Reset straight away, rather than waiting for the watchdog while the other core may still be
+    // feeding it
+    reset()
+}
+
+/// Reset the whole chip through the watchdog, which the next boot sees as a forced reset. Unlike
+/// resetting just the cores this always updates the watchdog's reason for the reset.
+pub fn reset() -> ! {
+    cortex_m::interrupt::disable();
+
+    // SAFETY: nothing else runs once this has reset the chip
+    let pac = unsafe { pac::Peripherals::steal() };
+    // Reset everything but the oscillators, like the HAL does when starting the watchdog, in case
+    // it hasn't been started yet
+    pac.PSM.wdsel().write(|w| {
+        // SAFETY: each bit selects a block to reset, any combination is valid
+        unsafe { w.bits(0x0001_ffff) };
+        w.xosc().clear_bit().rosc().clear_bit()
+    });
+    pac.WATCHDOG.ctrl().modify(|_, w| w.trigger().set_bit());
+
+    loop {
+        cortex_m::asm::nop();
+    }
+}
+
+/// Save the message of the panic described by `info`, along with where it happened.
+fn save(info: &PanicInfo) {
+    let mut record = Record {
+        magic: MAGIC,
+        len: 0,
+        msg: [0; 61],
+    };
+    let mut message = Message(&mut record);
+    if let Some(location) = info.location() {
+        let _ = write!(message, "{}:{}: ", location.file(), location.line());
+    }
+    let _ = write!(message, "{}", info.message());
+
+    // SAFETY: only written here, with interrupts disabled, and the device resets straight after
+    unsafe { core::ptr::write_volatile(core::ptr::addr_of_mut!(RECORD).cast(), record) };
+}
+
+/// The message saved by a panic just before the last reset, if there was one. The saved message
+/// is cleared so that it's only reported after the reset the panic caused.
+pub fn take() -> Option<SmolStr<61>> {
+    let record = core::ptr::addr_of_mut!(RECORD).cast::<Record>();
+    // SAFETY: every bit pattern is a valid `Record`, anything left by a power on is rejected by the
+    // magic and length checks
+    let saved = unsafe { core::ptr::read_volatile(record) };
+    unsafe { core::ptr::addr_of_mut!((*record).magic).write_volatile(0) };
+
+    if saved.magic != MAGIC {
+        return None;
+    }
+    let msg = saved.msg.get(..saved.len as usize)?;
+    SmolStr::new(core::str::from_utf8(msg).ok()?).ok()
+}
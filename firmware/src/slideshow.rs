@@ -0,0 +1,59 @@
+//! Cycling through the frames stored in flash slots without a host attached.
+
+use ἐννεάς_protocol::slots::SlideshowConfig;
+
+use crate::flash;
+
+pub struct Slideshow {
+    config: SlideshowConfig,
+    /// Timer ticks at which to show the next slot.
+    next_change: u64,
+    /// The slot currently on the panel, if the slideshow put it there.
+    current: Option<u8>,
+}
+
+impl Slideshow {
+    pub fn new(config: SlideshowConfig, now: u64) -> Self {
+        let mut slideshow = Self {
+            config,
+            next_change: 0,
+            current: None,
+        };
+        slideshow.configure(config, now);
+        slideshow
+    }
+
+    /// Replace the config, the next slot is shown after a whole interval.
+    pub fn configure(&mut self, config: SlideshowConfig, now: u64) {
+        self.config = config;
+        self.next_change = now + u64::from(config.interval()) * 1_000_000;
+    }
+
+    /// Record that something else is on the panel now, `slot` if it was a stored frame.
+    pub fn showing(&mut self, slot: Option<u8>) {
+        self.current = slot;
+    }
+
+    /// The slot to show next, if it's time to change. Empty slots are skipped, and nothing is
+    /// shown if the only stored slot is already on the panel.
+    pub fn next(&mut self, now: u64) -> Option<u8> {
+        if !self.config.is_running() || now < self.next_change {
+            return None;
+        }
+        self.next_change = now + u64::from(self.config.interval()) * 1_000_000;
+
+        let slots = self.config.slots();
+        let after = slots
+            .clone()
+            .filter(|&slot| self.current.is_none_or(|current| slot > current));
+        let next = after
+            .chain(slots)
+            .find(|&slot| slot < flash::SLOTS && flash::info(slot).checksum().is_some())?;
+
+        if Some(next) == self.current {
+            return None;
+        }
+        self.current = Some(next);
+        Some(next)
+    }
+}
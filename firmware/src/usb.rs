@@ -1,7 +1,6 @@
 use core::fmt::Write;
 use embedded_hal::{delay::DelayNs, digital::OutputPin};
-use heapless::String;
-use panic_halt as _;
+use heapless::{Deque, String};
 use usb_device::{
     bus::UsbBusAllocator,
     device::{StringDescriptors, UsbDevice, UsbDeviceBuilder, UsbVidPid},
@@ -9,7 +8,7 @@ use usb_device::{
 };
 use usbd_serial::{CdcAcmClass, SerialPort};
 use zerocopy::{IntoBytes, TryFromBytes};
-use ἐννεάς_protocol::{Command, Response, SmolStr};
+use ἐννεάς_protocol::{error::DeviceError, event::Event, Command, Response, SmolStr};
 
 use waveshare_rp2040_epaper_73::{
     hal::{usb::UsbBus, Timer},
@@ -18,16 +17,19 @@ use waveshare_rp2040_epaper_73::{
 
 pub struct Usb<'a> {
     said_hello: bool,
+    last_panic: Option<SmolStr<61>>,
     serial: SerialPort<'a, UsbBus>,
     commands: CommandPort<'a>,
     device: UsbDevice<'a, UsbBus>,
-    received_chunks: usize,
+    responses: Deque<Response, 8>,
+    events: Deque<Event, 8>,
 }
 
 impl<'a> Usb<'a> {
     pub fn new(
         bus: &'a UsbBusAllocator<UsbBus>,
         serial_number: &'a str,
+        last_panic: Option<SmolStr<61>>,
     ) -> Result<Self, crate::error::Infallible> {
         let serial = SerialPort::new_with_interface_names(bus, Some("ἐννεάς-log"), None);
         let commands = CommandPort::new(bus);
@@ -43,26 +45,107 @@ impl<'a> Usb<'a> {
 
         Ok(Self {
             said_hello: false,
+            last_panic,
             serial,
             commands,
             device,
-            received_chunks: 0,
+            responses: Deque::new(),
+            events: Deque::new(),
         })
     }
 
-    pub fn send_response(&mut self, response: Response) {
-        let mut text: String<62> = String::new();
-        let _ = writeln!(&mut text, "sending response: {}", response.as_bytes().len());
+    /// Write a line to the log interface, dropped if it doesn't fit in the buffers.
+    pub fn log(&mut self, args: core::fmt::Arguments<'_>) {
+        let mut text: String<64> = String::new();
+        let _ = text.write_fmt(args);
+        let _ = text.push('\n');
         let _ = self.serial.write(text.as_bytes());
+    }
 
-        match self.commands.write(response.as_bytes()) {
-            Ok(()) => {
-                let _ = self.serial.write(b"sent response\n");
+    /// Queue a response to be sent to the host, responses are sent in order as the host reads
+    /// them.
+    pub fn send_response(&mut self, response: Response) {
+        if self.responses.push_back(response).is_err() {
+            let _ = self
+                .serial
+                .write(b"response queue full, dropping response\n");
+        }
+    }
+
+    /// Queue a response like [`Self::send_response`], first waiting for the host to read earlier
+    /// responses if the queue is full. Returns `false` without queueing it if the host stops
+    /// reading for a while.
+    #[cfg(not(feature = "streaming"))]
+    pub fn send_response_waiting(&mut self, response: Response, timer: &mut Timer) -> bool {
+        let start = timer.get_counter().ticks();
+        while self.responses.is_full() {
+            if timer.get_counter().ticks() - start > 1_000_000 {
+                return false;
             }
-            Err(err) => {
-                let mut text: String<62> = String::new();
-                let _ = writeln!(&mut text, "error sending response: {err:?}");
-                let _ = self.serial.write(text.as_bytes());
+            self.device
+                .poll(&mut [&mut self.serial, &mut self.commands.class]);
+            self.flush_responses();
+        }
+        self.send_response(response);
+        true
+    }
+
+    /// Queue an event to be sent to the host after any responses, the oldest event is dropped if
+    /// the host hasn't been reading them.
+    pub fn send_event(&mut self, event: Event) {
+        if self.events.is_full() {
+            self.events.pop_front();
+            let _ = self
+                .serial
+                .write(b"event queue full, dropping oldest event\n");
+        }
+        let _ = self.events.push_back(event);
+    }
+
+    /// Give the host a short time to read any queued responses, for use before blocking the main
+    /// loop for a while.
+    pub fn flush(&mut self, timer: &mut Timer) {
+        let start = timer.get_counter().ticks();
+        while !self.responses.is_empty() && timer.get_counter().ticks() - start < 100_000 {
+            self.device
+                .poll(&mut [&mut self.serial, &mut self.commands.class]);
+            self.flush_responses();
+        }
+    }
+
+    fn flush_responses(&mut self) {
+        while let Some(response) = self.responses.front() {
+            match self.commands.write(response.as_bytes()) {
+                Ok(()) => {
+                    self.responses.pop_front();
+                }
+                Err(UsbError::WouldBlock) => break,
+                Err(err) => {
+                    self.responses.pop_front();
+                    let mut text: String<62> = String::new();
+                    let _ = writeln!(&mut text, "error sending response: {err:?}");
+                    let _ = self.serial.write(text.as_bytes());
+                }
+            }
+        }
+
+        // Events are only sent once there are no responses waiting, so they can't hold up
+        // commands
+        while self.responses.is_empty() {
+            let Some(&event) = self.events.front() else {
+                break;
+            };
+            match self.commands.write(Response::Event(event).as_bytes()) {
+                Ok(()) => {
+                    self.events.pop_front();
+                }
+                Err(UsbError::WouldBlock) => break,
+                Err(err) => {
+                    self.events.pop_front();
+                    let mut text: String<62> = String::new();
+                    let _ = writeln!(&mut text, "error sending event: {err:?}");
+                    let _ = self.serial.write(text.as_bytes());
+                }
             }
         }
     }
@@ -71,7 +154,7 @@ impl<'a> Usb<'a> {
         &mut self,
         timer: &mut Timer,
         activity: &mut LedActivity,
-    ) -> Result<Option<Result<Command, SmolStr<62>>>, crate::error::Infallible> {
+    ) -> Result<Option<Result<Command, DeviceError>>, crate::error::Infallible> {
         // A welcome message at the beginning
         if !self.said_hello && timer.get_counter().ticks() >= 2_000_000 {
             activity.set_high()?;
@@ -79,6 +162,14 @@ impl<'a> Usb<'a> {
 
             self.said_hello = true;
             let _ = self.serial.write(b"Hello, World!\n");
+            if let Some(msg) = self.last_panic {
+                // Written in pieces, the message alone can nearly fill a `String<64>`
+                let _ = self.serial.write(b"Reset after panicking: ");
+                let _ = self
+                    .serial
+                    .write(msg.to_str().unwrap_or("<invalid message>").as_bytes());
+                let _ = self.serial.write(b"\n");
+            }
 
             timer.delay_ms(500);
 
@@ -96,10 +187,13 @@ impl<'a> Usb<'a> {
             activity.set_low()?;
         }
 
-        if self
+        let polled = self
             .device
-            .poll(&mut [&mut self.serial, &mut self.commands.class])
-        {
+            .poll(&mut [&mut self.serial, &mut self.commands.class]);
+
+        self.flush_responses();
+
+        if polled {
             let mut buf = [0u8; 64];
             match self.serial.read(&mut buf) {
                 Err(_e) => {
@@ -129,38 +223,25 @@ impl<'a> Usb<'a> {
                 }
             }
 
+            // Every command gets a response, so stop reading commands while we can't queue any
+            // more responses; the host will see its writes stall until it reads them.
+            if self.responses.is_full() {
+                return Ok(None);
+            }
+
             if let Ok(Some(command)) = self.commands.read() {
                 let command = Command::try_read_from_bytes(&command);
                 match command {
                     Ok(command) => {
-                        match command {
-                            Command::Start { .. } => {
-                                let mut text: String<64> = String::new();
-                                let _ = writeln!(&mut text, "Start");
-                                let _ = self.serial.write(text.as_bytes());
-                                self.received_chunks = 0;
-                            }
-                            Command::Chunk { .. } => {
-                                self.received_chunks += 1;
-                            }
-                            Command::End { .. } => {
-                                let mut text: String<64> = String::new();
-                                let _ = writeln!(
-                                    &mut text,
-                                    "End, received {} chunks",
-                                    self.received_chunks
-                                );
-                                let _ = self.serial.write(text.as_bytes());
-                            }
-                        }
                         return Ok(Some(Ok(command)));
                     }
                     Err(err) => {
-                        let mut text: String<62> = String::new();
+                        let mut text: String<61> = String::new();
                         let _ = writeln!(&mut text, "{err}");
-                        return Ok(Some(Err(
-                            SmolStr::new(&text).unwrap_or_else(|_| SmolStr::new("error").unwrap())
-                        )));
+                        return Ok(Some(Err(DeviceError::InvalidCommand {
+                            msg: SmolStr::new(&text)
+                                .unwrap_or_else(|_| SmolStr::new("error").unwrap()),
+                        })));
                     }
                 }
             }
@@ -0,0 +1,58 @@
+//! The per-chunk cost of drawing chunks into a `Display7in3f`, one pixel at a time through
+//! embedded-graphics compared to packing them straight into its buffer.
+//!
+//! Run with `cargo bench --features embedded`.
+
+extern crate ennead_protocol as ἐννεάς_protocol;
+
+use std::{hint::black_box, time::Instant};
+
+use embedded_graphics_core::draw_target::DrawTarget;
+use epd_waveshare::epd7in3f::Display7in3f;
+use ἐννεάς_protocol::{Chunk, info::Panel};
+
+const ROUNDS: u32 = 20;
+
+/// Nanoseconds per chunk to run `draw` on every chunk of the frame.
+fn bench(chunks: &[Chunk], mut draw: impl FnMut(&Chunk)) -> f64 {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for chunk in chunks {
+            draw(black_box(chunk));
+        }
+    }
+    start.elapsed().as_nanos() as f64 / f64::from(ROUNDS) / chunks.len() as f64
+}
+
+fn main() {
+    let geometry = Panel::Epd7in3f.geometry();
+    let chunks: Vec<Chunk> = (0..geometry.chunks())
+        .map(|counter| {
+            let pixels: Vec<u8> = (0..geometry.chunk_len(counter))
+                .map(|i| ((i / 7 + counter) % 7) as u8)
+                .collect();
+            Chunk::new(geometry, counter, &pixels)
+        })
+        .collect();
+
+    let mut before = Display7in3f::default();
+    let draw_iter = bench(&chunks, |chunk| {
+        before
+            .draw_iter(chunk.oct_pixels(geometry).unwrap())
+            .unwrap()
+    });
+
+    let mut after = Display7in3f::default();
+    let packed = bench(&chunks, |chunk| {
+        chunk
+            .write_oct_buffer(geometry, after.get_mut_buffer())
+            .unwrap()
+    });
+
+    assert!(before.buffer() == after.buffer());
+
+    println!("{} chunks, {ROUNDS} rounds", chunks.len());
+    println!("draw_iter:        {draw_iter:>8.0} ns/chunk");
+    println!("write_oct_buffer: {packed:>8.0} ns/chunk");
+    println!("speedup:          {:>8.1}x", draw_iter / packed);
+}
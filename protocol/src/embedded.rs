@@ -1,26 +1,159 @@
 use embedded_graphics_core::{Pixel, geometry::Point};
 use epd_waveshare::color::OctColor;
 
-use super::{Color, Chunk};
+use super::{
+    Chunk, InvalidPixel, RegionChunk,
+    geometry::{Geometry, MAX_CHUNK_PIXELS},
+    info::Palette,
+};
 
+/// The `OctColor` for each index of [`Palette::Acep7`](crate::info::Palette::Acep7).
 pub const PALETTE: [OctColor; 7] = [OctColor::White, OctColor::Black, OctColor::Green, OctColor::Blue, OctColor::Red, OctColor::Yellow, OctColor::Orange];
 
+/// The palette index of each `OctColor` nibble value, `HiZ` has no palette entry so it maps to an
+/// invalid index.
+const NIBBLE_INDICES: [u8; 8] = [1, 0, 2, 3, 4, 5, 6, 7];
+
+/// The `OctColor` nibble value of each index of [`PALETTE`], the inverse of [`NIBBLE_INDICES`].
+const PALETTE_NIBBLES: [u8; 7] = [1, 0, 2, 3, 4, 5, 6];
+
+/// The nibble-packed `OctColor` bytes of a chunk's pixels, see [`Chunk::oct_bytes`].
+#[derive(Copy, Clone, Debug)]
+pub struct OctBytes {
+    data: [u8; MAX_CHUNK_PIXELS / 2],
+    len: usize,
+}
+
+impl core::ops::Deref for OctBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+/// Where the chunk with `counter` is in a nibble-packed `OctColor` buffer, chunks always start on
+/// an even pixel so they start on a byte boundary.
+fn oct_range(geometry: Geometry, counter: u16) -> core::ops::Range<usize> {
+    let (x, y) = geometry.origin(counter);
+    let len = usize::from(geometry.chunk_len(counter));
+    let start = usize::from(y) * usize::from(geometry.width().div_ceil(2)) + usize::from(x) / 2;
+    start..start + len.div_ceil(2)
+}
+
 impl Chunk {
-    pub fn oct_pixels(&self) -> impl Iterator<Item = Pixel<OctColor>> {
-        self.pixels().map(|((x, y), color)| Pixel(Point::new(i32::from(x), i32::from(y)), OctColor::from(color)))
+    /// Read the chunk with `counter` back out of a nibble-packed `OctColor` buffer, as used by
+    /// `Display7in3f`.
+    pub fn from_oct_buffer(geometry: Geometry, counter: u16, buffer: &[u8]) -> Self {
+        let len = usize::from(geometry.chunk_len(counter));
+        let nibbles = buffer[oct_range(geometry, counter)]
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xf])
+            .take(len)
+            .map(|nibble| NIBBLE_INDICES[usize::from(nibble & 0b111)]);
+        Self::from_indices(geometry, counter, nibbles)
+    }
+
+    /// The pixels of the chunk packed two to a byte the same as a `Display7in3f` buffer, or the
+    /// first pixel that isn't in [`Palette::Acep7`]. This is much cheaper than drawing
+    /// [`Self::oct_pixels`] one at a time.
+    pub fn oct_bytes(&self, geometry: Geometry) -> Result<OctBytes, InvalidPixel> {
+        let mut bytes = OctBytes {
+            data: [0; MAX_CHUNK_PIXELS / 2],
+            len: 0,
+        };
+        for (pixel, ((x, y), index)) in (0..).zip(self.pixels(geometry)) {
+            let Some(&nibble) = PALETTE_NIBBLES.get(usize::from(index)) else {
+                return Err(InvalidPixel { pixel, x, y, index });
+            };
+            let byte = &mut bytes.data[usize::from(pixel / 2)];
+            if pixel % 2 == 0 {
+                *byte = nibble << 4;
+                bytes.len += 1;
+            } else {
+                *byte |= nibble;
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Write the chunk into a nibble-packed `OctColor` buffer, the inverse of
+    /// [`Self::from_oct_buffer`], or return the first pixel that isn't in [`Palette::Acep7`]
+    /// without changing the buffer.
+    pub fn write_oct_buffer(
+        &self,
+        geometry: Geometry,
+        buffer: &mut [u8],
+    ) -> Result<(), InvalidPixel> {
+        let bytes = self.oct_bytes(geometry)?;
+        buffer[oct_range(geometry, self.counter())].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    /// The pixels of the chunk, or the first pixel that isn't in [`Palette::Acep7`].
+    pub fn oct_pixels(
+        &self,
+        geometry: Geometry,
+    ) -> Result<impl Iterator<Item = Pixel<OctColor>>, InvalidPixel> {
+        self.check(geometry, Palette::Acep7)?;
+        Ok(self.pixels(geometry).map(|((x, y), index)| {
+            Pixel(
+                Point::new(i32::from(x), i32::from(y)),
+                PALETTE[usize::from(index)],
+            )
+        }))
+    }
+}
+
+impl RegionChunk {
+    /// The pixels of the region, or the first pixel that isn't in [`Palette::Acep7`].
+    pub fn oct_pixels(
+        &self,
+        geometry: Geometry,
+    ) -> Result<impl Iterator<Item = Pixel<OctColor>>, InvalidPixel> {
+        self.check(geometry, Palette::Acep7)?;
+        Ok(self.pixels(geometry).map(|((x, y), index)| {
+            Pixel(
+                Point::new(i32::from(x), i32::from(y)),
+                PALETTE[usize::from(index)],
+            )
+        }))
     }
 }
 
-impl From<Color> for OctColor {
-    fn from(color: Color) -> Self {
-        match color {
-            Color::White => Self::White,
-            Color::Black => Self::Black,
-            Color::Green => Self::Green,
-            Color::Blue => Self::Blue,
-            Color::Red => Self::Red,
-            Color::Yellow => Self::Yellow,
-            Color::Orange => Self::Orange,
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use crate::{Chunk, info::Panel};
+
+    #[test]
+    fn oct_round_trip() {
+        let geometry = Panel::Epd7in3f.geometry();
+        let mut buffer =
+            vec![0; usize::from(geometry.width().div_ceil(2)) * usize::from(geometry.height())];
+
+        let chunks = (0..geometry.chunks()).map(|counter| {
+            let pixels: vec::Vec<u8> = (0..geometry.chunk_len(counter))
+                .map(|i| ((i / 3 + counter) % 7) as u8)
+                .collect();
+            Chunk::new(geometry, counter, &pixels)
+        });
+        for chunk in chunks.clone() {
+            chunk.write_oct_buffer(geometry, &mut buffer).unwrap();
         }
+        for chunk in chunks {
+            let read = Chunk::from_oct_buffer(geometry, chunk.counter(), &buffer);
+            assert_eq!(read.data(), chunk.data(), "chunk {}", chunk.counter());
+        }
+    }
+
+    #[test]
+    fn oct_bytes_invalid_pixel() {
+        let geometry = Panel::Epd7in3f.geometry();
+        let err = Chunk::new(geometry, 0, &[0, 1, 7])
+            .oct_bytes(geometry)
+            .unwrap_err();
+        assert_eq!((err.pixel, err.index), (2, 7));
     }
 }
@@ -0,0 +1,131 @@
+//! The errors the device reports in `Response::Err`, with enough context for the host to explain
+//! what went wrong.
+
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes, byteorder::little_endian as le};
+
+use crate::SmolStr;
+
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(u8)]
+pub enum DeviceError {
+    InvalidCommand {
+        msg: SmolStr<61>,
+    } = 0,
+    NotStarted {
+        _unused: [u8; 61],
+    } = 1,
+    CounterOutOfRange {
+        counter: le::U16,
+        _unused: [u8; 59],
+    } = 2,
+    RegionOutOfBounds {
+        x: le::U16,
+        y: le::U16,
+        len: le::U16,
+        _unused: [u8; 55],
+    } = 3,
+    InvalidCompressed {
+        counter: le::U16,
+        _unused: [u8; 59],
+    } = 4,
+    InvalidPixel {
+        pixel: le::U16,
+        x: le::U16,
+        y: le::U16,
+        index: u8,
+        _unused: [u8; 54],
+    } = 5,
+    Discarded {
+        _unused: [u8; 61],
+    } = 6,
+    ChecksumMismatch {
+        expected: le::U32,
+        actual: le::U32,
+        _unused: [u8; 53],
+    } = 7,
+    Busy {
+        _unused: [u8; 61],
+    } = 8,
+    Other {
+        msg: SmolStr<61>,
+    } = 9,
+    InvalidSlot {
+        slot: u8,
+        _unused: [u8; 60],
+    } = 10,
+    EmptySlot {
+        slot: u8,
+        _unused: [u8; 60],
+    } = 11,
+    OutOfOrder {
+        expected: le::U16,
+        counter: le::U16,
+        _unused: [u8; 57],
+    } = 12,
+}
+
+impl From<crate::InvalidPixel> for DeviceError {
+    fn from(err: crate::InvalidPixel) -> Self {
+        Self::InvalidPixel {
+            pixel: err.pixel.into(),
+            x: err.x.into(),
+            y: err.y.into(),
+            index: err.index,
+            _unused: [0; 54],
+        }
+    }
+}
+
+impl core::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::InvalidCommand { msg } => write!(
+                f,
+                "invalid command: {}",
+                msg.to_str().unwrap_or("<invalid message>")
+            ),
+            Self::NotStarted { .. } => write!(f, "no frame in progress"),
+            Self::CounterOutOfRange { counter, .. } => {
+                write!(f, "chunk counter {counter} out of range")
+            }
+            Self::RegionOutOfBounds { x, y, len, .. } => {
+                write!(f, "region of {len} pixels at ({x}, {y}) out of bounds")
+            }
+            Self::InvalidCompressed { counter, .. } => {
+                write!(f, "invalid compressed data for chunk {counter}")
+            }
+            Self::InvalidPixel {
+                pixel, x, y, index, ..
+            } => write!(
+                f,
+                "invalid palette index {index} for pixel {pixel} at ({x}, {y})"
+            ),
+            Self::Discarded { .. } => write!(f, "previous frame was discarded"),
+            Self::ChecksumMismatch {
+                expected, actual, ..
+            } => write!(
+                f,
+                "checksum mismatch: expected {:#010x}, got {:#010x}",
+                expected.get(),
+                actual.get()
+            ),
+            Self::Busy { .. } => write!(f, "device is busy"),
+            Self::Other { msg } => write!(f, "{}", msg.to_str().unwrap_or("<invalid message>")),
+            Self::InvalidSlot { slot, .. } => write!(f, "no slot {slot}"),
+            Self::EmptySlot { slot, .. } => write!(f, "slot {slot} is empty"),
+            Self::OutOfOrder {
+                expected, counter, ..
+            } => write!(f, "chunk {counter} out of order, expected chunk {expected}"),
+        }
+    }
+}
+
+impl core::fmt::Debug for DeviceError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_tuple("DeviceError")
+            .field(&format_args!("{self}"))
+            .finish()
+    }
+}
+
+impl core::error::Error for DeviceError {}
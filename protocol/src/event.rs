@@ -0,0 +1,113 @@
+//! Things happening on the device that it tells the host about without being asked, sent as
+//! `Response::Event` whenever the host next reads responses.
+
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes, byteorder::little_endian as le};
+
+use crate::SmolStr;
+
+/// Why the device last started up.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone, Debug, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+#[repr(u8)]
+pub enum ResetReason {
+    PowerOn = 0,
+    /// The device stopped feeding its watchdog without panicking.
+    Watchdog = 1,
+    /// The host asked the device to reboot.
+    Forced = 2,
+    /// The device panicked, `Command::LastPanic` says where.
+    Panic = 3,
+}
+
+/// Why the device last started up, in reply to `Command::ResetReason` for hosts that missed
+/// `Event::Booted`.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct LastReset {
+    reason: ResetReason,
+    _unused: [u8; 61],
+}
+
+impl LastReset {
+    pub fn new(reason: ResetReason) -> Self {
+        Self {
+            reason,
+            _unused: [0; 61],
+        }
+    }
+
+    pub fn reason(&self) -> ResetReason {
+        self.reason
+    }
+}
+
+impl core::fmt::Debug for LastReset {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_tuple("LastReset").field(&self.reason).finish()
+    }
+}
+
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(u8)]
+pub enum Event {
+    Booted {
+        reason: ResetReason,
+        _unused: [u8; 60],
+    } = 0,
+    /// Each refresh is numbered, counting the refreshes from [`crate::FrameStatus::refreshes`].
+    Refreshed {
+        refresh: le::U16,
+        _unused: [u8; 59],
+    } = 1,
+    RefreshFailed {
+        refresh: le::U16,
+        msg: SmolStr<59>,
+    } = 2,
+    FrameDiscarded {
+        _unused: [u8; 61],
+    } = 3,
+    // 4 and 5 are kept for button presses and low battery, once the board exposes those pins
+    Panicked {
+        msg: SmolStr<61>,
+    } = 6,
+}
+
+impl Event {
+    /// The number of the refresh a `Refreshed` or `RefreshFailed` event is about.
+    pub fn refresh(&self) -> Option<u16> {
+        match self {
+            Self::Refreshed { refresh, .. } | Self::RefreshFailed { refresh, .. } => {
+                Some(refresh.get())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Display for Event {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Booted { reason, .. } => write!(f, "booted after {} reset", reason.as_ref()),
+            Self::Refreshed { refresh, .. } => write!(f, "refresh {refresh} finished"),
+            Self::RefreshFailed { refresh, msg } => write!(
+                f,
+                "refresh {refresh} failed: {}",
+                msg.to_str().unwrap_or("<invalid message>")
+            ),
+            Self::FrameDiscarded { .. } => write!(f, "stale frame discarded"),
+            Self::Panicked { msg } => write!(
+                f,
+                "panicked during the previous run: {}",
+                msg.to_str().unwrap_or("<invalid message>")
+            ),
+        }
+    }
+}
+
+impl core::fmt::Debug for Event {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_tuple("Event")
+            .field(&format_args!("{self}"))
+            .finish()
+    }
+}
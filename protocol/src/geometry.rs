@@ -0,0 +1,194 @@
+//! The layout of a display's frame, and how it is split into chunks.
+
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes, byteorder::little_endian as le};
+
+/// Bytes of packed pixel data in each `Chunk`.
+pub(crate) const CHUNK_BYTES: usize = 60;
+
+/// Bytes of packed pixel data in each `RegionChunk`.
+pub(crate) const REGION_BYTES: usize = 57;
+
+/// The most pixels a chunk can hold, at the lowest bit depth.
+pub(crate) const MAX_CHUNK_PIXELS: usize = CHUNK_BYTES * 8;
+
+/// How many bits each pixel's palette index is packed into.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BitDepth {
+    One = 1,
+    Two = 2,
+    Three = 3,
+    Four = 4,
+}
+
+impl BitDepth {
+    pub const fn bits(self) -> u8 {
+        self as u8
+    }
+
+    /// The most colours a palette at this depth can have.
+    pub const fn colors(self) -> u16 {
+        1 << self.bits()
+    }
+}
+
+/// The resolution and bit depth of a display's frame, which determine how it's split into
+/// chunks.
+///
+/// Each row of the frame is split into chunks of [`Geometry::chunk_pixels`] pixels, the last
+/// chunk of a row is padded if the width isn't a multiple of that. Chunks are counted along each
+/// row, then down the rows.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone, PartialEq, Eq)]
+#[repr(C)]
+pub struct Geometry {
+    width: le::U16,
+    height: le::U16,
+    depth: BitDepth,
+}
+
+impl Geometry {
+    pub const fn new(width: u16, height: u16, depth: BitDepth) -> Self {
+        Self {
+            width: le::U16::new(width),
+            height: le::U16::new(height),
+            depth,
+        }
+    }
+
+    pub const fn width(&self) -> u16 {
+        self.width.get()
+    }
+
+    pub const fn height(&self) -> u16 {
+        self.height.get()
+    }
+
+    pub const fn depth(&self) -> BitDepth {
+        self.depth
+    }
+
+    /// How many pixels each chunk holds.
+    pub const fn chunk_pixels(&self) -> u16 {
+        (CHUNK_BYTES * 8 / self.depth.bits() as usize) as u16
+    }
+
+    /// The most pixels each region chunk can hold.
+    pub const fn region_pixels(&self) -> u16 {
+        let pixels = REGION_BYTES * 8 / self.depth.bits() as usize;
+        if pixels > u8::MAX as usize {
+            u8::MAX as u16
+        } else {
+            pixels as u16
+        }
+    }
+
+    /// Whether the frame has some pixels and few enough chunks to count them, the other methods
+    /// may panic for a geometry that isn't valid, such as one received from a faulty device.
+    pub const fn is_valid(&self) -> bool {
+        self.width() > 0
+            && self.height() > 0
+            && self.chunks_per_row().checked_mul(self.height()).is_some()
+    }
+
+    pub const fn chunks_per_row(&self) -> u16 {
+        self.width().div_ceil(self.chunk_pixels())
+    }
+
+    /// Number of chunks making up a whole frame.
+    pub const fn chunks(&self) -> u16 {
+        self.chunks_per_row() * self.height()
+    }
+
+    /// The position of the first pixel of the chunk with `counter`.
+    pub const fn origin(&self, counter: u16) -> (u16, u16) {
+        let per_row = self.chunks_per_row();
+        ((counter % per_row) * self.chunk_pixels(), counter / per_row)
+    }
+
+    /// How many pixels of the chunk with `counter` are within the frame.
+    pub const fn chunk_len(&self, counter: u16) -> u16 {
+        let (x, _) = self.origin(counter);
+        let remaining = self.width() - x;
+        if remaining < self.chunk_pixels() {
+            remaining
+        } else {
+            self.chunk_pixels()
+        }
+    }
+}
+
+impl core::fmt::Debug for Geometry {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("Geometry")
+            .field("width", &self.width())
+            .field("height", &self.height())
+            .field("depth", &self.depth)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitDepth, Geometry};
+    use crate::info::Panel;
+
+    #[test]
+    fn padded_last_chunk() {
+        // 160 pixels a chunk, so each row is 3 whole chunks and one of 120 pixels
+        let geometry = Geometry::new(600, 448, BitDepth::Three);
+        assert_eq!(geometry.chunk_pixels(), 160);
+        assert_eq!(geometry.chunks_per_row(), 4);
+        assert_eq!(geometry.chunks(), 4 * 448);
+        assert_eq!(geometry.origin(3), (480, 0));
+        assert_eq!(geometry.chunk_len(3), 120);
+        assert_eq!(geometry.origin(4), (0, 1));
+        assert_eq!(geometry.chunk_len(4), 160);
+        assert_eq!(geometry.origin(geometry.chunks() - 1), (480, 447));
+        assert_eq!(geometry.chunk_len(geometry.chunks() - 1), 120);
+    }
+
+    #[test]
+    fn whole_chunks() {
+        let geometry = Geometry::new(800, 480, BitDepth::Three);
+        assert_eq!(geometry.chunks_per_row(), 5);
+        assert_eq!(geometry.origin(4), (640, 0));
+        assert_eq!(geometry.chunk_len(4), 160);
+        assert_eq!(geometry.origin(5), (0, 1));
+    }
+
+    #[test]
+    fn chunks_cover_rows() {
+        for panel in [
+            Panel::Epd7in3f,
+            Panel::Epd7in3e,
+            Panel::Epd7in5V2,
+            Panel::Epd7in5B,
+            Panel::Epd4in01f,
+            Panel::Epd5in65f,
+            Panel::Epd13in3e,
+            Panel::Epd4in2,
+        ] {
+            let geometry = panel.geometry();
+            assert!(geometry.is_valid(), "{panel:?}");
+            let mut next = (0, 0);
+            for counter in 0..geometry.chunks() {
+                assert_eq!(geometry.origin(counter), next, "{panel:?} chunk {counter}");
+                let x = next.0 + geometry.chunk_len(counter);
+                next = if x == geometry.width() {
+                    (0, next.1 + 1)
+                } else {
+                    (x, next.1)
+                };
+            }
+            assert_eq!(next, (0, geometry.height()), "{panel:?}");
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(!Geometry::new(0, 480, BitDepth::One).is_valid());
+        assert!(!Geometry::new(800, 0, BitDepth::One).is_valid());
+        assert!(!Geometry::new(u16::MAX, u16::MAX, BitDepth::Four).is_valid());
+        assert!(Geometry::new(u16::MAX, 1, BitDepth::Four).is_valid());
+    }
+}
@@ -1,8 +1,11 @@
-use std::vec::Vec;
+use std::{vec, vec::Vec};
 
-use image::{GenericImageView, Rgb};
+use image::{GenericImageView, Rgb, RgbImage, math::Rect};
 
-use super::{Chunk, Color, Command, HEIGHT, WIDTH};
+use super::{
+    Chunk, Color, Command, InvalidPixel, RegionChunk, frame_checksum, geometry::Geometry,
+    info::Palette, rle::CompressedChunk,
+};
 
 const WHITE: Rgb<u8> = image::Rgb([255, 255, 255]);
 const BLACK: Rgb<u8> = image::Rgb([0, 0, 0]);
@@ -11,30 +14,256 @@ const BLUE: Rgb<u8> = image::Rgb([0, 0, 255]);
 const RED: Rgb<u8> = image::Rgb([255, 0, 0]);
 const YELLOW: Rgb<u8> = image::Rgb([255, 255, 0]);
 const ORANGE: Rgb<u8> = image::Rgb([255, 128, 0]);
+const LIGHT_GREY: Rgb<u8> = image::Rgb([170, 170, 170]);
+const DARK_GREY: Rgb<u8> = image::Rgb([85, 85, 85]);
 
-pub const PALETTE: [image::Rgb<u8>; 7] = [WHITE, BLACK, GREEN, BLUE, RED, YELLOW, ORANGE];
+impl Palette {
+    /// The colours of the palette, in index order.
+    pub fn rgb(self) -> Vec<Rgb<u8>> {
+        self.colors()
+            .iter()
+            .map(|&color| Rgb::from(color))
+            .collect()
+    }
+}
+
+/// The palette index of `pixel`, panics if it isn't one of the palette's colours.
+fn index(palette: Palette, pixel: Rgb<u8>) -> u8 {
+    Color::try_from(pixel)
+        .ok()
+        .and_then(|color| palette.index(color))
+        .expect("non-palettized image")
+}
+
+/// The palette indices of the pixels of `image` in chunk order, the last chunk of each row is
+/// padded with index 0.
+fn frame_pixels(
+    image: &impl GenericImageView<Pixel = Rgb<u8>>,
+    geometry: Geometry,
+    palette: Palette,
+) -> Vec<u8> {
+    assert!(image.dimensions() == (u32::from(geometry.width()), u32::from(geometry.height())));
+
+    (0..geometry.chunks())
+        .flat_map(|counter| {
+            let (x, y) = geometry.origin(counter);
+            let len = geometry.chunk_len(counter);
+            (0..geometry.chunk_pixels()).map(move |i| {
+                if i < len {
+                    index(palette, image.get_pixel(u32::from(x + i), u32::from(y)))
+                } else {
+                    0
+                }
+            })
+        })
+        .collect()
+}
+
+impl Chunk {
+    /// The chunks making up `image` as a whole frame.
+    pub fn from_image(
+        image: &impl GenericImageView<Pixel = Rgb<u8>>,
+        geometry: Geometry,
+        palette: Palette,
+    ) -> Vec<Self> {
+        let pixels = frame_pixels(image, geometry, palette);
+        (0..)
+            .zip(pixels.chunks(usize::from(geometry.chunk_pixels())))
+            .map(|(counter, pixels)| Self::new(geometry, counter, pixels))
+            .collect()
+    }
+
+    /// The image made up of `chunks`, any parts of the frame not covered by a chunk are left
+    /// black.
+    pub fn to_image(
+        chunks: &[Self],
+        geometry: Geometry,
+        palette: Palette,
+    ) -> Result<RgbImage, InvalidPixel> {
+        let mut image = RgbImage::new(u32::from(geometry.width()), u32::from(geometry.height()));
+        for chunk in chunks {
+            chunk.check(geometry, palette)?;
+            for ((x, y), index) in chunk.pixels(geometry) {
+                let color = palette.color(index).expect("checked above");
+                image.put_pixel(u32::from(x), u32::from(y), Rgb::from(color));
+            }
+        }
+        Ok(image)
+    }
+}
 
 impl Command {
-    pub fn from_image(image: &impl GenericImageView<Pixel = Rgb<u8>>) -> Vec<Self> {
-        assert!(image.dimensions() == (WIDTH, HEIGHT));
-
-        [Self::Start { _unused: [0; 62] }]
-            .into_iter()
-            .chain(
-                image
-                    .pixels()
-                    .map(|(_, _, pixel)| pixel)
-                    .array_chunks()
-                    .zip(0..)
-                    .map(|(pixels, counter)| {
-                        Self::Chunk(Chunk::new(
-                            counter,
-                            pixels
-                                .map(|pixel| Color::try_from(pixel).expect("non-palettized image")),
+    /// Commands to send `image` as a whole frame, run-length encoding the chunks with
+    /// `Command::Compressed` if `compress` is set.
+    pub fn from_image(
+        image: &impl GenericImageView<Pixel = Rgb<u8>>,
+        geometry: Geometry,
+        palette: Palette,
+        compress: bool,
+    ) -> Vec<Self> {
+        let start = Self::Start {
+            keep: false,
+            base_checksum: 0.into(),
+            _unused: [0; 57],
+        };
+        let changed = vec![true; usize::from(geometry.chunks())];
+        Self::from_pixels(
+            geometry,
+            &frame_pixels(image, geometry, palette),
+            Some(start),
+            &changed,
+            compress,
+        )
+    }
+
+    /// Commands to finish sending `image` as a whole frame when the device already has the first
+    /// `contiguous` chunks of it.
+    ///
+    /// Compressed chunks start from `contiguous` rather than resending the compressed chunks of
+    /// the whole frame that overlap it, since a streaming device only accepts chunks in order.
+    pub fn from_image_resume(
+        image: &impl GenericImageView<Pixel = Rgb<u8>>,
+        geometry: Geometry,
+        palette: Palette,
+        contiguous: u16,
+        compress: bool,
+    ) -> Vec<Self> {
+        let changed: Vec<bool> = (0..geometry.chunks())
+            .map(|counter| counter >= contiguous)
+            .collect();
+        Self::from_pixels(
+            geometry,
+            &frame_pixels(image, geometry, palette),
+            None,
+            &changed,
+            compress,
+        )
+    }
+
+    /// Commands to send `image` as a whole frame, sending only the chunks that differ from
+    /// `previous`, the chunks of the frame the device should currently have.
+    ///
+    /// If the device's frame doesn't match `previous` it starts from a blank frame instead, and
+    /// reports the chunks that weren't sent as missing in response to `Command::End`.
+    pub fn from_image_delta(
+        image: &impl GenericImageView<Pixel = Rgb<u8>>,
+        geometry: Geometry,
+        palette: Palette,
+        previous: &[Chunk],
+        compress: bool,
+    ) -> Vec<Self> {
+        assert!(previous.len() == usize::from(geometry.chunks()));
+
+        let pixels = frame_pixels(image, geometry, palette);
+        let start = Self::Start {
+            keep: true,
+            base_checksum: frame_checksum(previous.iter().copied()).into(),
+            _unused: [0; 57],
+        };
+        let changed: Vec<bool> = (0..)
+            .zip(pixels.chunks(usize::from(geometry.chunk_pixels())))
+            .zip(previous)
+            .map(|((counter, pixels), previous)| {
+                Chunk::new(geometry, counter, pixels).data() != previous.data()
+            })
+            .collect();
+        Self::from_pixels(geometry, &pixels, Some(start), &changed, compress)
+    }
+
+    /// Commands to send the chunks of the frame `pixels` which are marked as `changed`, after
+    /// `start` if the frame isn't already in progress.
+    fn from_pixels(
+        geometry: Geometry,
+        pixels: &[u8],
+        start: Option<Self>,
+        changed: &[bool],
+        compress: bool,
+    ) -> Vec<Self> {
+        let chunk_pixels = usize::from(geometry.chunk_pixels());
+        let checksum = frame_checksum(
+            (0..)
+                .zip(pixels.chunks(chunk_pixels))
+                .map(|(counter, pixels)| Chunk::new(geometry, counter, pixels)),
+        );
+
+        let mut commands: Vec<Self> = start.into_iter().collect();
+
+        let mut counter = 0;
+        while counter < changed.len() {
+            let run = changed[counter..]
+                .iter()
+                .take_while(|&&changed| changed)
+                .count();
+            if run == 0 {
+                counter += 1;
+                continue;
+            }
+
+            let end = counter + run;
+            while counter < end {
+                let remaining = &pixels[counter * chunk_pixels..end * chunk_pixels];
+                match compress
+                    .then(|| CompressedChunk::compress(geometry, counter as u16, remaining))
+                {
+                    Some((compressed, count)) if count > 0 => {
+                        commands.push(Self::Compressed(compressed));
+                        counter += count;
+                    }
+                    _ => {
+                        let pixels = &remaining[..chunk_pixels];
+                        commands.push(Self::Chunk(Chunk::new(geometry, counter as u16, pixels)));
+                        counter += 1;
+                    }
+                }
+            }
+        }
+
+        commands.push(Self::End {
+            checksum: checksum.into(),
+            skip_checksum: false,
+            _unused: [0; 57],
+        });
+
+        commands
+    }
+
+    /// Commands to update just the `region` of the frame to match `image`, leaving the rest of
+    /// the frame as is.
+    pub fn from_image_region(
+        image: &impl GenericImageView<Pixel = Rgb<u8>>,
+        geometry: Geometry,
+        palette: Palette,
+        region: Rect,
+    ) -> Vec<Self> {
+        let (width, height) = (u32::from(geometry.width()), u32::from(geometry.height()));
+        assert!(image.dimensions() == (width, height));
+        assert!(region.x + region.width <= width && region.y + region.height <= height);
+
+        let view = image.view(region.x, region.y, region.width, region.height);
+        let region_pixels = usize::from(geometry.region_pixels());
+
+        (0..region.height)
+            .flat_map(|y| {
+                let row: Vec<u8> = (0..region.width)
+                    .map(|x| index(palette, view.get_pixel(x, y)))
+                    .collect();
+                (0..)
+                    .zip(row.chunks(region_pixels))
+                    .map(|(i, pixels)| {
+                        Self::Region(RegionChunk::new(
+                            geometry,
+                            (region.x as usize + i * region_pixels) as u16,
+                            (region.y + y) as u16,
+                            pixels,
                         ))
-                    }),
-            )
-            .chain([Self::End { _unused: [0; 62] }])
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .chain([Self::End {
+                checksum: 0.into(),
+                skip_checksum: true,
+                _unused: [0; 57],
+            }])
             .collect()
     }
 }
@@ -49,6 +278,8 @@ impl From<Color> for Rgb<u8> {
             Color::Red => RED,
             Color::Yellow => YELLOW,
             Color::Orange => ORANGE,
+            Color::LightGrey => LIGHT_GREY,
+            Color::DarkGrey => DARK_GREY,
         }
     }
 }
@@ -65,7 +296,97 @@ impl TryFrom<Rgb<u8>> for Color {
             RED => Color::Red,
             YELLOW => Color::Yellow,
             ORANGE => Color::Orange,
+            LIGHT_GREY => Color::LightGrey,
+            DARK_GREY => Color::DarkGrey,
             _ => return Err(()),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use image::RgbImage;
+
+    use crate::{Command, geometry::Geometry, info::Panel};
+
+    fn image(geometry: Geometry, pattern: impl Fn(u32, u32) -> usize) -> RgbImage {
+        let colors = Panel::Epd5in65f.palette().rgb();
+        RgbImage::from_fn(
+            u32::from(geometry.width()),
+            u32::from(geometry.height()),
+            |x, y| colors[pattern(x, y) % colors.len()],
+        )
+    }
+
+    /// Resuming partway through what would be one compressed command has to start a new one from
+    /// there, rather than resending chunks the device already has.
+    #[test]
+    fn resume_within_compressed() {
+        let geometry = Panel::Epd5in65f.geometry();
+        let palette = Panel::Epd5in65f.palette();
+        let image = image(geometry, |_, y| (y / 40) as usize);
+
+        let full = Command::from_image(&image, geometry, palette, true);
+        let straddling = full
+            .iter()
+            .find_map(|command| match command {
+                Command::Compressed(compressed) if compressed.counters().len() > 2 => {
+                    Some(compressed.counters())
+                }
+                _ => None,
+            })
+            .expect("no compressed run to resume within");
+        let contiguous = straddling.start + 1;
+
+        let resumed = Command::from_image_resume(&image, geometry, palette, contiguous, true);
+        let counters: Vec<u16> = resumed
+            .iter()
+            .flat_map(|command| match command {
+                Command::Chunk(chunk) => chunk.counter()..chunk.counter() + 1,
+                Command::Compressed(compressed) => compressed.counters(),
+                _ => 0..0,
+            })
+            .collect();
+        assert!(counters.iter().copied().eq(contiguous..geometry.chunks()));
+
+        let checksum = |commands: &[Command]| match commands.last() {
+            Some(&Command::End { checksum, .. }) => checksum.get(),
+            command => panic!("expected end, got {command:?}"),
+        };
+        assert_eq!(checksum(&resumed), checksum(&full));
+        assert!(!matches!(resumed[0], Command::Start { .. }));
+    }
+
+    /// The firmware checksums the chunks it reads back out of its framebuffer, which has to match
+    /// the checksum of the chunks sent, including the padding of each row's last chunk.
+    #[test]
+    #[cfg(feature = "embedded")]
+    fn checksum_matches_oct_buffer() {
+        use std::vec;
+
+        use crate::{Chunk, frame_checksum};
+
+        let geometry = Panel::Epd5in65f.geometry();
+        let palette = Panel::Epd5in65f.palette();
+        assert_ne!(geometry.width() % geometry.chunk_pixels(), 0);
+
+        let image = image(geometry, |x, y| (x / 3 + y) as usize);
+        let commands = Command::from_image(&image, geometry, palette, false);
+        let Some(&Command::End { checksum, .. }) = commands.last() else {
+            panic!("no end command");
+        };
+
+        let mut buffer =
+            vec![0; usize::from(geometry.width().div_ceil(2)) * usize::from(geometry.height())];
+        for command in &commands {
+            if let Command::Chunk(chunk) = command {
+                chunk.write_oct_buffer(geometry, &mut buffer).unwrap();
+            }
+        }
+        let read = (0..geometry.chunks())
+            .map(|counter| Chunk::from_oct_buffer(geometry, counter, &buffer));
+        assert_eq!(frame_checksum(read), checksum.get());
+    }
+}
@@ -0,0 +1,291 @@
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes, byteorder::little_endian as le};
+
+use super::{
+    Color, SmolStr, SmolStrError,
+    geometry::{BitDepth, Geometry},
+};
+
+/// Bumped whenever a change to the protocol means an older host or device can't talk to a newer
+/// one.
+pub const PROTOCOL_VERSION: u16 = 4;
+
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone, Debug, strum::AsRefStr)]
+#[repr(u8)]
+pub enum Panel {
+    #[strum(serialize = "Waveshare 7.3inch e-Paper (F)")]
+    Epd7in3f = 0,
+    #[strum(serialize = "Waveshare 7.3inch e-Paper (E)")]
+    Epd7in3e = 1,
+    #[strum(serialize = "Waveshare 4.01inch e-Paper (F)")]
+    Epd4in01f = 2,
+    #[strum(serialize = "Waveshare 5.65inch e-Paper (F)")]
+    Epd5in65f = 3,
+    #[strum(serialize = "Waveshare 13.3inch e-Paper (E)")]
+    Epd13in3e = 4,
+    #[strum(serialize = "Waveshare 7.5inch e-Paper V2")]
+    Epd7in5V2 = 5,
+    #[strum(serialize = "Waveshare 7.5inch e-Paper (B)")]
+    Epd7in5B = 6,
+    #[strum(serialize = "Waveshare 4.2inch e-Paper")]
+    Epd4in2 = 7,
+}
+
+impl Panel {
+    /// The palette the panel is normally driven with.
+    pub const fn palette(self) -> Palette {
+        match self {
+            Self::Epd7in3f | Self::Epd4in01f | Self::Epd5in65f => Palette::Acep7,
+            Self::Epd7in3e | Self::Epd13in3e => Palette::Spectra6,
+            Self::Epd7in5V2 => Palette::BlackWhite,
+            Self::Epd7in5B => Palette::BlackWhiteRed,
+            Self::Epd4in2 => Palette::Grey4,
+        }
+    }
+
+    /// The geometry of the panel, when driven with its normal palette.
+    pub const fn geometry(self) -> Geometry {
+        let (width, height) = match self {
+            Self::Epd7in3f | Self::Epd7in3e | Self::Epd7in5V2 | Self::Epd7in5B => (800, 480),
+            Self::Epd4in01f => (640, 400),
+            Self::Epd5in65f => (600, 448),
+            Self::Epd13in3e => (1200, 1600),
+            Self::Epd4in2 => (400, 300),
+        };
+        Geometry::new(width, height, self.palette().depth())
+    }
+}
+
+/// The colours a panel can show, a pixel's palette index is its position in
+/// [`Palette::colors`].
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone, Debug, strum::AsRefStr)]
+#[repr(u8)]
+pub enum Palette {
+    #[strum(serialize = "7-colour ACeP")]
+    Acep7 = 0,
+    #[strum(serialize = "6-colour Spectra")]
+    Spectra6 = 1,
+    #[strum(serialize = "black/white")]
+    BlackWhite = 2,
+    #[strum(serialize = "black/white/red")]
+    BlackWhiteRed = 3,
+    #[strum(serialize = "4-grey")]
+    Grey4 = 4,
+}
+
+impl Palette {
+    pub const fn colors(self) -> &'static [Color] {
+        match self {
+            Self::Acep7 => &[
+                Color::White,
+                Color::Black,
+                Color::Green,
+                Color::Blue,
+                Color::Red,
+                Color::Yellow,
+                Color::Orange,
+            ],
+            Self::Spectra6 => &[
+                Color::White,
+                Color::Black,
+                Color::Green,
+                Color::Blue,
+                Color::Red,
+                Color::Yellow,
+            ],
+            Self::BlackWhite => &[Color::White, Color::Black],
+            Self::BlackWhiteRed => &[Color::White, Color::Black, Color::Red],
+            Self::Grey4 => &[
+                Color::White,
+                Color::LightGrey,
+                Color::DarkGrey,
+                Color::Black,
+            ],
+        }
+    }
+
+    /// The smallest bit depth that can hold every index of the palette.
+    pub const fn depth(self) -> BitDepth {
+        match self.colors().len() {
+            0..=2 => BitDepth::One,
+            3..=4 => BitDepth::Two,
+            5..=8 => BitDepth::Three,
+            _ => BitDepth::Four,
+        }
+    }
+
+    pub fn index(self, color: Color) -> Option<u8> {
+        self.colors()
+            .iter()
+            .position(|&c| c == color)
+            .map(|index| index as u8)
+    }
+
+    pub fn color(self, index: u8) -> Option<Color> {
+        self.colors().get(usize::from(index)).copied()
+    }
+}
+
+/// Optional protocol features supported by the device.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct Features(le::U32);
+
+impl Features {
+    /// `Command::End` checksums are verified.
+    pub const CHECKSUM: Self = Self::bit(0);
+    /// Missing chunks are reported with `Response::Incomplete` and can be retransmitted.
+    pub const RETRANSMIT: Self = Self::bit(1);
+    /// Parts of the frame can be updated with `Command::Region`.
+    pub const REGION: Self = Self::bit(2);
+    /// Run-length encoded chunks can be sent with `Command::Compressed`.
+    pub const COMPRESSED: Self = Self::bit(3);
+    /// `Command::Start` can keep the current frame, so that only changed chunks need sending.
+    pub const DELTA: Self = Self::bit(4);
+    /// A frame in progress can be discarded with `Command::Abort`, and is discarded if no more
+    /// commands arrive for a while.
+    pub const ABORT: Self = Self::bit(5);
+    /// `Command::Status` reports how far a frame in progress got, so sending can resume from there.
+    pub const RESUME: Self = Self::bit(6);
+    /// `Response::Event` is sent without any command to respond to, including once the refresh
+    /// after a frame finishes.
+    pub const EVENTS: Self = Self::bit(7);
+    /// `Command::ReadBack` streams the current frame back as `Response::Chunk`s.
+    pub const READBACK: Self = Self::bit(8);
+    /// Frames can be saved to and shown from flash slots with `Command::SaveSlot` and friends.
+    pub const SLOTS: Self = Self::bit(9);
+    /// The device can cycle through stored frames by itself, configured with
+    /// `Command::Slideshow`.
+    pub const SLIDESHOW: Self = Self::bit(10);
+    /// The device can be reset, or rebooted into its USB bootloader, with `Command::Reboot`.
+    pub const REBOOT: Self = Self::bit(11);
+    /// Chunks are written straight to the panel as they arrive, so they must be sent in order and
+    /// there's no framebuffer for regions, deltas, reading back or saving to a slot. Chunks can't
+    /// be retransmitted, a frame can only be resumed from `FrameStatus::contiguous`.
+    pub const STREAMING: Self = Self::bit(12);
+    /// The device resets itself when it panics, then reports the panic message with
+    /// `Event::Panicked` and `Command::LastPanic`.
+    pub const PANIC: Self = Self::bit(13);
+    /// The device resets itself if its main loop or a refresh stops making progress, and reports
+    /// why it last reset with `Command::ResetReason`.
+    pub const WATCHDOG: Self = Self::bit(14);
+
+    const NAMES: &[(Self, &str)] = &[
+        (Self::CHECKSUM, "checksum"),
+        (Self::RETRANSMIT, "retransmit"),
+        (Self::REGION, "region"),
+        (Self::COMPRESSED, "compressed"),
+        (Self::DELTA, "delta"),
+        (Self::ABORT, "abort"),
+        (Self::RESUME, "resume"),
+        (Self::EVENTS, "events"),
+        (Self::READBACK, "readback"),
+        (Self::SLOTS, "slots"),
+        (Self::SLIDESHOW, "slideshow"),
+        (Self::REBOOT, "reboot"),
+        (Self::STREAMING, "streaming"),
+        (Self::PANIC, "panic"),
+        (Self::WATCHDOG, "watchdog"),
+    ];
+
+    const fn bit(bit: u32) -> Self {
+        Self(le::U32::new(1 << bit))
+    }
+
+    pub const fn empty() -> Self {
+        Self(le::U32::ZERO)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0.get() & other.0.get() == other.0.get()
+    }
+
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        Self::NAMES
+            .iter()
+            .filter(move |(feature, _)| self.contains(*feature))
+            .map(|(_, name)| *name)
+    }
+}
+
+impl core::ops::BitOr for Features {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self((self.0.get() | other.0.get()).into())
+    }
+}
+
+impl core::fmt::Debug for Features {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
+
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct DeviceInfo {
+    protocol_version: le::U16,
+    panel: Panel,
+    palette: Palette,
+    geometry: Geometry,
+    features: Features,
+    firmware_version: SmolStr<24>,
+    _unused: [u8; 25],
+}
+
+impl DeviceInfo {
+    pub fn new(
+        panel: Panel,
+        palette: Palette,
+        geometry: Geometry,
+        features: Features,
+        firmware_version: &str,
+    ) -> Result<Self, SmolStrError> {
+        Ok(Self {
+            protocol_version: PROTOCOL_VERSION.into(),
+            panel,
+            palette,
+            geometry,
+            features,
+            firmware_version: SmolStr::new(firmware_version)?,
+            _unused: [0; 25],
+        })
+    }
+
+    pub fn protocol_version(&self) -> u16 {
+        self.protocol_version.get()
+    }
+
+    pub fn panel(&self) -> Panel {
+        self.panel
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    pub fn firmware_version(&self) -> Result<&str, SmolStrError> {
+        self.firmware_version.to_str()
+    }
+}
+
+impl core::fmt::Debug for DeviceInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("DeviceInfo")
+            .field("protocol_version", &self.protocol_version())
+            .field("panel", &self.panel)
+            .field("palette", &self.palette)
+            .field("geometry", &self.geometry)
+            .field("features", &self.features)
+            .field("firmware_version", &self.firmware_version())
+            .finish()
+    }
+}
@@ -1,31 +1,33 @@
 #![no_std]
-#![feature(iter_array_chunks, array_try_map)]
 
-#[cfg(feature = "std")]
+#[cfg(any(test, feature = "std"))]
 extern crate std;
 
 use zerocopy::{byteorder::little_endian as le, Immutable, IntoBytes, KnownLayout, TryFromBytes};
 
+use geometry::{BitDepth, CHUNK_BYTES, Geometry, REGION_BYTES};
+use info::Palette;
+
+pub mod checksum;
+pub mod error;
+pub mod event;
+pub mod geometry;
+pub mod info;
+pub mod panic;
+pub mod rle;
+pub mod slots;
+
 #[cfg(feature = "std")]
 pub mod image;
 
 #[cfg(feature = "embedded")]
 pub mod embedded;
 
-pub const WIDTH: u32 = 800;
-pub const HEIGHT: u32 = 480;
-
-#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone, Debug)]
-#[repr(C)]
-pub struct SubChunk {
-    data: [u8; 3],
-}
-
 #[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
 #[repr(C)]
 pub struct Chunk {
     counter: le::U16,
-    subchunks: [SubChunk; 20],
+    data: [u8; CHUNK_BYTES],
 }
 
 impl core::fmt::Debug for Chunk {
@@ -34,7 +36,30 @@ impl core::fmt::Debug for Chunk {
         let mut debug = f.debug_struct("Chunk");
         debug.field("counter", &u16::from(self.counter));
         if alternate {
-            debug.field("subchunks", &self.subchunks);
+            debug.field("data", &self.data);
+        }
+        debug.finish()
+    }
+}
+
+/// A horizontal run of pixels at an arbitrary position, for updating part of the frame.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct RegionChunk {
+    x: le::U16,
+    y: le::U16,
+    len: u8,
+    data: [u8; REGION_BYTES],
+}
+
+impl core::fmt::Debug for RegionChunk {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let alternate = f.alternate();
+        let mut debug = f.debug_struct("RegionChunk");
+        debug.field("origin", &self.origin());
+        debug.field("len", &self.len);
+        if alternate {
+            debug.field("data", &self.data);
         }
         debug.finish()
     }
@@ -43,17 +68,72 @@ impl core::fmt::Debug for Chunk {
 #[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone, strum::AsRefStr)]
 #[repr(u8)]
 pub enum Command {
-    Start { _unused: [u8; 62] } = 0,
+    Start { keep: bool, base_checksum: le::U32, _unused: [u8; 57] } = 0,
     Chunk(Chunk) = 1,
-    End { _unused: [u8; 62] } = 2,
+    End { checksum: le::U32, skip_checksum: bool, _unused: [u8; 57] } = 2,
+    Info { _unused: [u8; 62] } = 3,
+    Region(RegionChunk) = 4,
+    Compressed(rle::CompressedChunk) = 5,
+    Abort { _unused: [u8; 62] } = 6,
+    Status { _unused: [u8; 62] } = 7,
+    ReadBack { _unused: [u8; 62] } = 8,
+    SaveSlot { slot: u8, _unused: [u8; 61] } = 9,
+    ListSlots { _unused: [u8; 62] } = 10,
+    DeleteSlot { slot: u8, _unused: [u8; 61] } = 11,
+    ShowSlot { slot: u8, _unused: [u8; 61] } = 12,
+    Slideshow(slots::SlideshowConfig) = 13,
+    Reboot { bootloader: bool, _unused: [u8; 61] } = 14,
+    LastPanic { _unused: [u8; 62] } = 15,
+    ResetReason { _unused: [u8; 62] } = 16,
 }
 
 impl core::fmt::Debug for Command {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
-            Self::Start { .. } => f.debug_tuple("Command::Start").finish(),
+            Self::Start {
+                keep,
+                base_checksum,
+                ..
+            } => f
+                .debug_struct("Command::Start")
+                .field("keep", keep)
+                .field(
+                    "base_checksum",
+                    &format_args!("{:#010x}", base_checksum.get()),
+                )
+                .finish(),
             Self::Chunk(chunk) => f.debug_tuple("Command::Chunk").field(chunk).finish(),
-            Self::End { .. } => f.debug_tuple("Command::End").finish(),
+            Self::End {
+                checksum,
+                skip_checksum,
+                ..
+            } => f
+                .debug_struct("Command::End")
+                .field("checksum", &format_args!("{:#010x}", checksum.get()))
+                .field("skip_checksum", skip_checksum)
+                .finish(),
+            Self::Info { .. } => f.debug_tuple("Command::Info").finish(),
+            Self::Region(region) => f.debug_tuple("Command::Region").field(region).finish(),
+            Self::Compressed(compressed) => f
+                .debug_tuple("Command::Compressed")
+                .field(compressed)
+                .finish(),
+            Self::Abort { .. } => f.debug_tuple("Command::Abort").finish(),
+            Self::Status { .. } => f.debug_tuple("Command::Status").finish(),
+            Self::ReadBack { .. } => f.debug_tuple("Command::ReadBack").finish(),
+            Self::SaveSlot { slot, .. } => f.debug_tuple("Command::SaveSlot").field(slot).finish(),
+            Self::ListSlots { .. } => f.debug_tuple("Command::ListSlots").finish(),
+            Self::DeleteSlot { slot, .. } => {
+                f.debug_tuple("Command::DeleteSlot").field(slot).finish()
+            }
+            Self::ShowSlot { slot, .. } => f.debug_tuple("Command::ShowSlot").field(slot).finish(),
+            Self::Slideshow(config) => f.debug_tuple("Command::Slideshow").field(config).finish(),
+            Self::Reboot { bootloader, .. } => f
+                .debug_struct("Command::Reboot")
+                .field("bootloader", bootloader)
+                .finish(),
+            Self::LastPanic { .. } => f.debug_tuple("Command::LastPanic").finish(),
+            Self::ResetReason { .. } => f.debug_tuple("Command::ResetReason").finish(),
         }
     }
 }
@@ -63,16 +143,165 @@ impl core::fmt::Debug for Command {
 pub struct SmolStr<const CAP: usize>([u8; CAP]);
 
 impl<const CAP: usize> SmolStr<CAP> {
-    pub fn new(s: &str) -> Result<Self, ()> {
-        if s.as_bytes().contains(&0) {
-            return Err(());
+    pub fn new(s: &str) -> Result<Self, SmolStrError> {
+        if s.len() > CAP || s.as_bytes().contains(&0) {
+            return Err(SmolStrError);
         }
-        Ok(Self(s.as_bytes().try_into().map_err(|_| ())?))
+        let mut bytes = [0; CAP];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Self(bytes))
     }
 
-    pub fn to_str(&self) -> Result<&str, ()> {
+    pub fn to_str(&self) -> Result<&str, SmolStrError> {
         let end = self.0.iter().position(|&b| b == 0).unwrap_or(CAP);
-        core::str::from_utf8(&self.0[..end]).map_err(|_| ())
+        core::str::from_utf8(&self.0[..end]).map_err(|_| SmolStrError)
+    }
+}
+
+/// A string that's too long for a `SmolStr` or contains a NUL, or a `SmolStr` that isn't UTF-8.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SmolStrError;
+
+impl core::fmt::Display for SmolStrError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "string too long, containing a NUL or not UTF-8")
+    }
+}
+
+impl core::error::Error for SmolStrError {}
+
+/// A half-open range of chunk counters.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct ChunkRange {
+    start: le::U16,
+    end: le::U16,
+}
+
+impl ChunkRange {
+    pub const EMPTY: Self = Self::new(0..0);
+
+    pub const fn new(range: core::ops::Range<u16>) -> Self {
+        Self {
+            start: le::U16::new(range.start),
+            end: le::U16::new(range.end),
+        }
+    }
+
+    pub fn range(&self) -> core::ops::Range<u16> {
+        self.start.get()..self.end.get()
+    }
+}
+
+impl core::fmt::Debug for ChunkRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        self.range().fmt(f)
+    }
+}
+
+/// The missing chunks of a frame, `ranges` lists as many missing ranges as fit.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct MissingChunks {
+    total: le::U16,
+    count: u8,
+    ranges: [ChunkRange; 14],
+    _unused: [u8; 3],
+}
+
+impl MissingChunks {
+    pub fn new(total: u16, ranges: impl IntoIterator<Item = core::ops::Range<u16>>) -> Self {
+        let mut count = 0;
+        let mut array = [ChunkRange::EMPTY; 14];
+        for (slot, range) in array.iter_mut().zip(ranges) {
+            *slot = ChunkRange::new(range);
+            count += 1;
+        }
+        Self {
+            total: total.into(),
+            count,
+            ranges: array,
+            _unused: [0; 3],
+        }
+    }
+
+    /// The total number of missing chunks, including those in ranges that didn't fit.
+    pub fn total(&self) -> u16 {
+        self.total.get()
+    }
+
+    pub fn ranges(&self) -> impl Iterator<Item = core::ops::Range<u16>> + '_ {
+        self.listed().iter().map(ChunkRange::range)
+    }
+
+    fn listed(&self) -> &[ChunkRange] {
+        &self.ranges[..usize::from(self.count).min(self.ranges.len())]
+    }
+}
+
+impl core::fmt::Debug for MissingChunks {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("MissingChunks")
+            .field("total", &self.total())
+            .field("ranges", &self.listed())
+            .finish()
+    }
+}
+
+/// How far the device got receiving the whole frame in progress, so that an interrupted transfer
+/// can carry on from where it stopped, and how many refreshes it has started.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct FrameStatus {
+    in_progress: bool,
+    contiguous: le::U16,
+    received: le::U16,
+    refreshes: le::U16,
+    _unused: [u8; 55],
+}
+
+impl FrameStatus {
+    pub fn new(in_progress: bool, contiguous: u16, received: u16, refreshes: u16) -> Self {
+        Self {
+            in_progress,
+            contiguous: contiguous.into(),
+            received: received.into(),
+            refreshes: refreshes.into(),
+            _unused: [0; 55],
+        }
+    }
+
+    /// Whether a whole frame has been started and not yet finished or discarded.
+    pub fn in_progress(&self) -> bool {
+        self.in_progress
+    }
+
+    /// How many chunks from the start of the frame have all been received, so sending can resume
+    /// from the chunk with this counter.
+    pub fn contiguous(&self) -> u16 {
+        self.contiguous.get()
+    }
+
+    /// The total number of chunks received, including any after a gap.
+    pub fn received(&self) -> u16 {
+        self.received.get()
+    }
+
+    /// How many refreshes the device has started since booting, wrapping around. The events for
+    /// each refresh carry its number, so the next refresh will be `refreshes + 1`.
+    pub fn refreshes(&self) -> u16 {
+        self.refreshes.get()
+    }
+}
+
+impl core::fmt::Debug for FrameStatus {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("FrameStatus")
+            .field("in_progress", &self.in_progress)
+            .field("contiguous", &self.contiguous())
+            .field("received", &self.received())
+            .field("refreshes", &self.refreshes())
+            .finish()
     }
 }
 
@@ -80,19 +309,39 @@ impl<const CAP: usize> SmolStr<CAP> {
 #[repr(u8)]
 pub enum Response {
     Ok { _unused: [u8; 62] } = 0,
-    Err { msg: SmolStr<62> } = 2,
+    Err(error::DeviceError) = 2,
+    Incomplete(MissingChunks) = 3,
+    Info(info::DeviceInfo) = 4,
+    Status(FrameStatus) = 5,
+    Event(event::Event) = 6,
+    Chunk(Chunk) = 7,
+    Slots(slots::SlotList) = 8,
+    Panic(panic::LastPanic) = 9,
+    Reset(event::LastReset) = 10,
 }
 
 impl core::fmt::Debug for Response {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             Self::Ok { .. } => f.debug_tuple("Response::Ok").finish(),
-            Self::Err { msg } => f.debug_tuple("Response::Err").field(&msg.to_str()).finish(),
+            Self::Err(err) => f.debug_tuple("Response::Err").field(err).finish(),
+            Self::Incomplete(missing) => f
+                .debug_tuple("Response::Incomplete")
+                .field(missing)
+                .finish(),
+            Self::Info(info) => f.debug_tuple("Response::Info").field(info).finish(),
+            Self::Status(status) => f.debug_tuple("Response::Status").field(status).finish(),
+            Self::Event(event) => f.debug_tuple("Response::Event").field(event).finish(),
+            Self::Chunk(chunk) => f.debug_tuple("Response::Chunk").field(chunk).finish(),
+            Self::Slots(slots) => f.debug_tuple("Response::Slots").field(slots).finish(),
+            Self::Panic(panic) => f.debug_tuple("Response::Panic").field(panic).finish(),
+            Self::Reset(reset) => f.debug_tuple("Response::Reset").field(reset).finish(),
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The colours panels can show, see [`info::Palette`] for which colours a specific panel has.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Color {
     White,
     Black,
@@ -101,101 +350,230 @@ pub enum Color {
     Red,
     Yellow,
     Orange,
+    LightGrey,
+    DarkGrey,
 }
 
+const _: () = assert!(core::mem::size_of::<RegionChunk>() == 62);
+const _: () = assert!(core::mem::size_of::<rle::CompressedChunk>() == 62);
 const _: () = assert!(core::mem::size_of::<Command>() == 63);
+const _: () = assert!(core::mem::size_of::<Response>() == 63);
 
 impl Chunk {
-    pub fn new(counter: u16, pixels: [Color; 160]) -> Self {
-        let pixels: [[Color; 8]; 20] = core::array::from_fn({
-            let mut iter = pixels.into_iter().array_chunks();
-            move |_| iter.next().unwrap()
-        });
+    /// Create the chunk with `counter` from the palette indices of its pixels, panics if there are
+    /// more pixels than fit in a chunk, any left over space is filled with index 0.
+    pub fn new(geometry: Geometry, counter: u16, pixels: &[u8]) -> Self {
+        assert!(pixels.len() <= usize::from(geometry.chunk_pixels()));
+        Self::from_indices(geometry, counter, pixels.iter().copied())
+    }
 
+    pub(crate) fn from_indices(
+        geometry: Geometry,
+        counter: u16,
+        indices: impl IntoIterator<Item = u8>,
+    ) -> Self {
         Self {
             counter: counter.into(),
-            subchunks: pixels.map(SubChunk::from),
+            data: pack(geometry.depth(), indices),
         }
     }
+
+    /// The packed pixel data, as covered by the frame checksum.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// The checksum sent with `Command::End`, covering the data of every chunk in counter order.
+pub fn frame_checksum(chunks: impl IntoIterator<Item = Chunk>) -> u32 {
+    let mut crc = checksum::Crc32::new();
+    for chunk in chunks {
+        crc.update(chunk.data());
+    }
+    crc.finish()
 }
 
 impl Chunk {
-    pub fn pixels(self) -> impl Iterator<Item = ((u16, u16), Color)> {
-        let (x, y) = (
-            (u16::from(self.counter) % 5) * 160,
-            u16::from(self.counter) / 5,
-        );
-        // TODO: how to handle error here
-        self.subchunks
-            .into_iter()
-            .flat_map(|subchunk| <[Color; 8]>::try_from(subchunk).unwrap())
+    pub fn counter(&self) -> u16 {
+        self.counter.get()
+    }
+
+    /// The palette indices of the pixels of the chunk that are within the frame.
+    pub fn pixels(self, geometry: Geometry) -> impl Iterator<Item = ((u16, u16), u8)> {
+        let (x, y) = geometry.origin(self.counter.get());
+        unpack(geometry.depth(), self.data)
+            .take(usize::from(geometry.chunk_len(self.counter.get())))
             .zip(x..)
             .map(move |(color, x)| ((x, y), color))
     }
+
+    /// Check that every pixel of the chunk within the frame is in `palette`.
+    pub fn check(&self, geometry: Geometry, palette: Palette) -> Result<(), InvalidPixel> {
+        check_pixels(self.pixels(geometry), palette)
+    }
 }
 
-impl From<[Color; 8]> for SubChunk {
-    fn from(pixels: [Color; 8]) -> Self {
-        let [a, b, c, d, e, f, g, h] = pixels.map(u8::from);
+impl RegionChunk {
+    /// Create a region chunk starting at `(x, y)` from the palette indices of its pixels, panics
+    /// if there are more than [`Geometry::region_pixels`] pixels.
+    pub fn new(geometry: Geometry, x: u16, y: u16, pixels: &[u8]) -> Self {
+        assert!(pixels.len() <= usize::from(geometry.region_pixels()));
 
         Self {
-            data: [
-                (a << 5) | (b << 2) | (c >> 1),
-                (c << 7) | (d << 4) | (e << 1) | (f >> 2),
-                (f << 6) | (g << 3) | h,
-            ],
+            x: x.into(),
+            y: y.into(),
+            len: pixels.len() as u8,
+            data: pack(geometry.depth(), pixels.iter().copied()),
         }
     }
+
+    pub fn origin(&self) -> (u16, u16) {
+        (self.x.get(), self.y.get())
+    }
+
+    pub fn len(&self) -> usize {
+        usize::from(self.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The palette indices of the pixels of the region, stopping early if the region runs past
+    /// the largest possible `x`, since it comes from the host.
+    pub fn pixels(self, geometry: Geometry) -> impl Iterator<Item = ((u16, u16), u8)> {
+        let (x, y) = self.origin();
+        unpack(geometry.depth(), self.data)
+            .take(self.len())
+            .zip(0..)
+            .map_while(move |(color, offset)| Some(((x.checked_add(offset)?, y), color)))
+    }
+
+    /// Check that every pixel of the region is in `palette`.
+    pub fn check(&self, geometry: Geometry, palette: Palette) -> Result<(), InvalidPixel> {
+        check_pixels(self.pixels(geometry), palette)
+    }
 }
 
-impl TryFrom<SubChunk> for [Color; 8] {
-    type Error = <Color as TryFrom<u8>>::Error;
+/// A pixel whose palette index isn't in the palette.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InvalidPixel {
+    /// The offset of the pixel within its chunk.
+    pub pixel: u16,
+    pub x: u16,
+    pub y: u16,
+    pub index: u8,
+}
 
-    fn try_from(subchunk: SubChunk) -> Result<Self, Self::Error> {
-        let [a, b, c] = subchunk.data;
+impl core::fmt::Display for InvalidPixel {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let Self { pixel, x, y, index } = self;
+        write!(
+            f,
+            "invalid palette index {index} for pixel {pixel} at ({x}, {y})"
+        )
+    }
+}
 
-        let pixels = [
-            (a >> 5) & 0b111,
-            (a >> 2) & 0b111,
-            (a << 1) & 0b110 | (b >> 7) & 0b001,
-            (b >> 4) & 0b111,
-            (b >> 1) & 0b111,
-            (b << 2) & 0b100 | (c >> 6) & 0b011,
-            (c >> 3) & 0b111,
-            (c >> 0) & 0b111,
-        ];
+impl core::error::Error for InvalidPixel {}
 
-        pixels.try_map(Color::try_from)
+fn check_pixels(
+    pixels: impl Iterator<Item = ((u16, u16), u8)>,
+    palette: Palette,
+) -> Result<(), InvalidPixel> {
+    for (pixel, ((x, y), index)) in (0..).zip(pixels) {
+        if palette.color(index).is_none() {
+            return Err(InvalidPixel { pixel, x, y, index });
+        }
     }
+    Ok(())
 }
 
-impl From<Color> for u8 {
-    fn from(pixel: Color) -> Self {
-        match pixel {
-            Color::White => 0,
-            Color::Black => 1,
-            Color::Green => 2,
-            Color::Blue => 3,
-            Color::Red => 4,
-            Color::Yellow => 5,
-            Color::Orange => 6,
+/// Pack palette indices of `depth` bits each, most significant bit first, any left over space is
+/// filled with index 0.
+fn pack<const N: usize>(depth: BitDepth, indices: impl IntoIterator<Item = u8>) -> [u8; N] {
+    let bits = usize::from(depth.bits());
+    let mut data = [0; N];
+    for (i, index) in indices.into_iter().take(N * 8 / bits).enumerate() {
+        for bit in 0..bits {
+            if index & (1 << (bits - 1 - bit)) != 0 {
+                let pos = i * bits + bit;
+                data[pos / 8] |= 0x80 >> (pos % 8);
+            }
         }
     }
+    data
 }
 
-impl TryFrom<u8> for Color {
-    type Error = ();
+/// Unpack palette indices of `depth` bits each, as packed by [`pack`].
+fn unpack<const N: usize>(depth: BitDepth, data: [u8; N]) -> impl Iterator<Item = u8> {
+    let bits = u32::from(depth.bits());
+    let mut bytes = data.into_iter();
+    // Bits read from `data` but not yet unpacked, in the low `available` bits
+    let (mut pending, mut available) = (0u32, 0);
+    core::iter::from_fn(move || {
+        while available < bits {
+            pending = (pending << 8) | u32::from(bytes.next()?);
+            available += 8;
+        }
+        available -= bits;
+        Some(((pending >> available) & ((1 << bits) - 1)) as u8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::{BitDepth, CHUNK_BYTES, MissingChunks, pack, unpack};
+
+    const DEPTHS: [BitDepth; 4] = [
+        BitDepth::One,
+        BitDepth::Two,
+        BitDepth::Three,
+        BitDepth::Four,
+    ];
+
+    #[test]
+    fn pack_round_trip() {
+        for depth in DEPTHS {
+            let colors = usize::from(depth.colors());
+            let indices =
+                (0..CHUNK_BYTES * 8 / usize::from(depth.bits())).map(|i| (i * 5 % colors) as u8);
+            let data: [u8; CHUNK_BYTES] = pack(depth, indices.clone());
+            assert!(unpack(depth, data).eq(indices), "{depth:?}");
+        }
+    }
+
+    #[test]
+    fn pack_layout() {
+        assert_eq!(
+            pack::<2>(BitDepth::Three, [0b101, 0b011, 0b111]),
+            [0b1010_1111, 0b1000_0000]
+        );
+        assert_eq!(pack::<2>(BitDepth::Four, [0x1, 0x2, 0x3]), [0x12, 0x30]);
+    }
+
+    #[test]
+    fn missing_chunks_full() {
+        let ranges: Vec<_> = (0..14).map(|i| i * 10..i * 10 + 3).collect();
+        let missing = MissingChunks::new(42, ranges.clone());
+        assert_eq!(missing.total(), 42);
+        assert!(missing.ranges().eq(ranges));
+    }
+
+    #[test]
+    fn missing_chunks_truncated() {
+        let missing = MissingChunks::new(1000, (0..20).map(|i| i * 50..i * 50 + 50));
+        assert_eq!(missing.total(), 1000);
+        assert!(missing.ranges().eq((0..14).map(|i| i * 50..i * 50 + 50)));
+    }
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        Ok(match value {
-            0 => Self::White,
-            1 => Self::Black,
-            2 => Self::Green,
-            3 => Self::Blue,
-            4 => Self::Red,
-            5 => Self::Yellow,
-            6 => Self::Orange,
-            _ => return Err(()),
-        })
+    #[test]
+    fn missing_chunks_invalid_count() {
+        let mut missing = MissingChunks::new(3, core::iter::once(4..7));
+        missing.count = 200;
+        assert_eq!(missing.ranges().count(), 14);
+        assert_eq!(missing.ranges().next(), Some(4..7));
     }
 }
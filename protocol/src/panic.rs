@@ -0,0 +1,38 @@
+//! The message the device saved the last time it panicked, before resetting itself.
+
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes};
+
+use crate::SmolStr;
+
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct LastPanic {
+    panicked: bool,
+    msg: SmolStr<61>,
+}
+
+impl LastPanic {
+    pub const NONE: Self = Self {
+        panicked: false,
+        msg: SmolStr([0; 61]),
+    };
+
+    pub fn new(msg: SmolStr<61>) -> Self {
+        Self {
+            panicked: true,
+            msg,
+        }
+    }
+
+    /// The panic message, if the device panicked before it last reset.
+    pub fn message(&self) -> Option<&str> {
+        self.panicked
+            .then(|| self.msg.to_str().unwrap_or("<invalid message>"))
+    }
+}
+
+impl core::fmt::Debug for LastPanic {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_tuple("LastPanic").field(&self.message()).finish()
+    }
+}
@@ -0,0 +1,263 @@
+//! Run-length encoding of consecutive chunks.
+//!
+//! Each run is encoded as either one byte `ccc0nnnn` for runs of 1 to 16 pixels, or two bytes
+//! `ccc1nnnn nnnnnnnn` for runs of 1 to 4096 pixels, where `ccc` is the palette index and `n` is
+//! the run length minus one. Runs may continue across chunk boundaries, and only palette indices
+//! up to 7 can be encoded.
+
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes, byteorder::little_endian as le};
+
+use super::{
+    Chunk,
+    geometry::{Geometry, MAX_CHUNK_PIXELS},
+};
+
+const DATA: usize = 58;
+const SHORT_RUN: u16 = 16;
+const LONG_RUN: u16 = 4096;
+const MAX_INDEX: u8 = 0b111;
+
+/// A run of `count` whole chunks starting at `counter`, run-length encoded.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct CompressedChunk {
+    counter: le::U16,
+    count: u8,
+    len: u8,
+    data: [u8; DATA],
+}
+
+impl core::fmt::Debug for CompressedChunk {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let alternate = f.alternate();
+        let mut debug = f.debug_struct("CompressedChunk");
+        debug.field("counters", &self.counters());
+        if alternate {
+            debug.field("data", &self.data());
+        }
+        debug.finish()
+    }
+}
+
+/// Encoder state, copied to allow backing out a chunk that doesn't fit.
+#[derive(Copy, Clone)]
+struct Encoder {
+    data: [u8; DATA],
+    len: usize,
+    run: Option<(u8, u16)>,
+}
+
+impl Encoder {
+    fn run_len(n: u16) -> usize {
+        if n <= SHORT_RUN { 1 } else { 2 }
+    }
+
+    fn emit(&mut self, color: u8, n: u16) -> bool {
+        let len = Self::run_len(n);
+        if self.len + len > DATA {
+            return false;
+        }
+        let n = n - 1;
+        if len == 1 {
+            self.data[self.len] = (color << 5) | n as u8;
+        } else {
+            self.data[self.len] = (color << 5) | 0b1_0000 | (n >> 8) as u8;
+            self.data[self.len + 1] = n as u8;
+        }
+        self.len += len;
+        true
+    }
+
+    fn push(&mut self, color: u8) -> bool {
+        if color > MAX_INDEX {
+            return false;
+        }
+        match self.run {
+            Some((run, n)) if run == color && n < LONG_RUN => {
+                self.run = Some((run, n + 1));
+                true
+            }
+            Some((run, n)) => {
+                self.run = Some((color, 1));
+                self.emit(run, n)
+            }
+            None => {
+                self.run = Some((color, 1));
+                true
+            }
+        }
+    }
+
+    /// Whether the pending run would still fit.
+    fn fits(&self) -> bool {
+        self.len + self.run.map_or(0, |(_, n)| Self::run_len(n)) <= DATA
+    }
+}
+
+impl CompressedChunk {
+    /// Compress as many chunks of `pixels` (the palette indices of consecutive chunks, the first
+    /// having `counter`) as fit in a single packet, returns how many were used alongside the
+    /// packet, this may be zero if the first chunk doesn't compress well enough or has indices
+    /// that can't be encoded.
+    pub fn compress(geometry: Geometry, counter: u16, pixels: &[u8]) -> (Self, usize) {
+        let mut encoder = Encoder {
+            data: [0; DATA],
+            len: 0,
+            run: None,
+        };
+
+        let mut count = 0;
+        let chunks = pixels.chunks(usize::from(geometry.chunk_pixels()));
+        for chunk in chunks.take(usize::from(u8::MAX)) {
+            let saved = encoder;
+            if !(chunk.iter().all(|&color| encoder.push(color)) && encoder.fits()) {
+                encoder = saved;
+                break;
+            }
+            count += 1;
+        }
+
+        if let Some((color, n)) = encoder.run {
+            let fitted = encoder.emit(color, n);
+            debug_assert!(fitted);
+        }
+
+        let compressed = Self {
+            counter: counter.into(),
+            count: count as u8,
+            len: encoder.len as u8,
+            data: encoder.data,
+        };
+
+        (compressed, count)
+    }
+
+    /// The counters of the chunks this covers.
+    pub fn counters(&self) -> core::ops::Range<u16> {
+        let start = self.counter.get();
+        start..start.saturating_add(u16::from(self.count))
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data[..usize::from(self.len).min(DATA)]
+    }
+
+    /// Decompress into the chunks this covers, yields an error and stops if the data is invalid.
+    pub fn chunks(&self, geometry: Geometry) -> impl Iterator<Item = Result<Chunk, ()>> + '_ {
+        let mut runs = Runs { data: self.data() };
+        let mut run = (0, 0);
+        let mut failed = false;
+
+        self.counters().map_while(move |counter| {
+            if failed {
+                return None;
+            }
+
+            let mut pixels = [0; MAX_CHUNK_PIXELS];
+            let pixels = &mut pixels[..usize::from(geometry.chunk_pixels())];
+            for pixel in &mut *pixels {
+                if run.1 == 0 {
+                    match runs.next() {
+                        Some(Ok(next)) => run = next,
+                        Some(Err(())) | None => {
+                            failed = true;
+                            return Some(Err(()));
+                        }
+                    }
+                }
+                *pixel = run.0;
+                run.1 -= 1;
+            }
+
+            Some(Ok(Chunk::new(geometry, counter, pixels)))
+        })
+    }
+}
+
+struct Runs<'a> {
+    data: &'a [u8],
+}
+
+impl Iterator for Runs<'_> {
+    type Item = Result<(u8, u16), ()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&first, rest) = self.data.split_first()?;
+        let color = first >> 5;
+        if first & 0b1_0000 == 0 {
+            self.data = rest;
+            Some(Ok((color, u16::from(first & 0b1111) + 1)))
+        } else {
+            let Some((&second, rest)) = rest.split_first() else {
+                return Some(Err(()));
+            };
+            self.data = rest;
+            Some(Ok((
+                color,
+                (u16::from(first & 0b1111) << 8 | u16::from(second)) + 1,
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::CompressedChunk;
+    use crate::{Chunk, geometry::Geometry, info::Panel};
+
+    const GEOMETRY: Geometry = Panel::Epd7in3f.geometry();
+
+    /// Compress `pixels` starting at chunk 7, check every chunk used decompresses to the original.
+    fn round_trip(pixels: &[u8]) -> usize {
+        let chunk_pixels = usize::from(GEOMETRY.chunk_pixels());
+        let (compressed, count) = CompressedChunk::compress(GEOMETRY, 7, pixels);
+        assert_eq!(compressed.counters(), 7..7 + count as u16);
+
+        let chunks: Vec<_> = compressed.chunks(GEOMETRY).collect();
+        assert_eq!(chunks.len(), count);
+        for ((counter, chunk), pixels) in (7..).zip(chunks).zip(pixels.chunks(chunk_pixels)) {
+            let chunk = chunk.unwrap();
+            assert_eq!(chunk.counter(), counter);
+            assert_eq!(chunk.data(), Chunk::new(GEOMETRY, counter, pixels).data());
+        }
+        count
+    }
+
+    #[test]
+    fn short_and_long_runs() {
+        // Runs of 1 to 40 pixels, some crossing chunk boundaries
+        let pixels: Vec<u8> = (0..40u8)
+            .flat_map(|n| [n % 7].repeat(usize::from(n) + 1))
+            .collect();
+        assert!(round_trip(&pixels) > 0);
+    }
+
+    #[test]
+    fn solid() {
+        let pixels = [3; 255 * 160];
+        assert_eq!(round_trip(&pixels), 255);
+    }
+
+    #[test]
+    fn incompressible() {
+        let pixels: Vec<u8> = (0..160).map(|i| (i % 7) as u8).collect();
+        assert_eq!(round_trip(&pixels), 0);
+    }
+
+    #[test]
+    fn invalid_index() {
+        let mut pixels = [0; 160];
+        pixels[10] = 8;
+        assert_eq!(round_trip(&pixels), 0);
+    }
+
+    #[test]
+    fn truncated() {
+        let (mut compressed, count) = CompressedChunk::compress(GEOMETRY, 0, &[1; 160]);
+        assert_eq!(count, 1);
+        compressed.len -= 1;
+        assert!(matches!(compressed.chunks(GEOMETRY).next(), Some(Err(()))));
+    }
+}
@@ -0,0 +1,190 @@
+//! Frames stored in the device's flash, so that it can show them again without a host attached.
+
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes, byteorder::little_endian as le};
+
+/// Whether a slot holds a frame, and which frame by the checksum of its chunks.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct SlotInfo {
+    used: bool,
+    checksum: le::U32,
+}
+
+impl SlotInfo {
+    pub const EMPTY: Self = Self {
+        used: false,
+        checksum: le::U32::ZERO,
+    };
+
+    pub fn new(checksum: u32) -> Self {
+        Self {
+            used: true,
+            checksum: checksum.into(),
+        }
+    }
+
+    /// The [`frame_checksum`](crate::frame_checksum) of the stored frame, if there is one.
+    pub fn checksum(&self) -> Option<u32> {
+        self.used.then(|| self.checksum.get())
+    }
+}
+
+impl core::fmt::Debug for SlotInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.checksum() {
+            Some(checksum) => write!(f, "SlotInfo({checksum:#010x})"),
+            None => write!(f, "SlotInfo(empty)"),
+        }
+    }
+}
+
+/// Every slot the device has, in slot order.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct SlotList {
+    count: u8,
+    slots: [SlotInfo; 12],
+    _unused: [u8; 1],
+}
+
+impl SlotList {
+    /// Any slots past the first 12 are left out, as they don't fit in a response.
+    pub fn new(slots: impl IntoIterator<Item = SlotInfo>) -> Self {
+        let mut count = 0;
+        let mut array = [SlotInfo::EMPTY; 12];
+        for (entry, slot) in array.iter_mut().zip(slots) {
+            *entry = slot;
+            count += 1;
+        }
+        Self {
+            count,
+            slots: array,
+            _unused: [0; 1],
+        }
+    }
+
+    pub fn slots(&self) -> &[SlotInfo] {
+        &self.slots[..usize::from(self.count).min(self.slots.len())]
+    }
+}
+
+impl core::fmt::Debug for SlotList {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_list().entries(self.slots()).finish()
+    }
+}
+
+/// Which slots the device cycles through by itself, and how often, the slideshow is stopped if
+/// no slots are selected.
+#[derive(IntoBytes, TryFromBytes, KnownLayout, Immutable, Copy, Clone)]
+#[repr(C)]
+pub struct SlideshowConfig {
+    interval: le::U32,
+    slots: le::U16,
+    _unused: [u8; 56],
+}
+
+impl SlideshowConfig {
+    pub const STOPPED: Self = Self {
+        interval: le::U32::ZERO,
+        slots: le::U16::ZERO,
+        _unused: [0; 56],
+    };
+
+    /// The most slots a slideshow can select from, slots `0..SLOTS`.
+    pub const SLOTS: u8 = 16;
+
+    /// Show the next of `slots` every `interval` seconds, or the first slot that's too high.
+    pub fn new(interval: u32, slots: impl IntoIterator<Item = u8>) -> Result<Self, SlotOutOfRange> {
+        let mut mask = 0u16;
+        for slot in slots {
+            if slot >= Self::SLOTS {
+                return Err(SlotOutOfRange(slot));
+            }
+            mask |= 1 << slot;
+        }
+        Ok(Self {
+            interval: interval.into(),
+            slots: mask.into(),
+            _unused: [0; 56],
+        })
+    }
+
+    /// Seconds between changing slots.
+    pub fn interval(&self) -> u32 {
+        self.interval.get()
+    }
+
+    /// The selected slots in ascending order.
+    pub fn slots(&self) -> impl Iterator<Item = u8> + Clone {
+        let mask = self.slots.get();
+        (0..Self::SLOTS).filter(move |slot| mask & (1 << slot) != 0)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.slots.get() != 0 && self.interval.get() != 0
+    }
+}
+
+/// A slot too high for a [`SlideshowConfig`] to select.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SlotOutOfRange(pub u8);
+
+impl core::fmt::Display for SlotOutOfRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(
+            f,
+            "slot {} is too high for a slideshow, which can only use slots 0 to {}",
+            self.0,
+            SlideshowConfig::SLOTS - 1
+        )
+    }
+}
+
+impl core::error::Error for SlotOutOfRange {}
+
+impl core::fmt::Debug for SlideshowConfig {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("SlideshowConfig")
+            .field("interval", &self.interval())
+            .field("slots", &format_args!("{:#06x}", self.slots.get()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SlideshowConfig, SlotInfo, SlotList, SlotOutOfRange};
+
+    #[test]
+    fn slot_list() {
+        let list = SlotList::new([SlotInfo::new(7), SlotInfo::EMPTY]);
+        assert_eq!(list.slots().len(), 2);
+        assert_eq!(list.slots()[0].checksum(), Some(7));
+        assert_eq!(list.slots()[1].checksum(), None);
+    }
+
+    #[test]
+    fn slot_list_truncated() {
+        let list = SlotList::new((0..20).map(SlotInfo::new));
+        assert_eq!(list.slots().len(), 12);
+        assert_eq!(list.slots()[11].checksum(), Some(11));
+    }
+
+    #[test]
+    fn slideshow_slots() {
+        let config = SlideshowConfig::new(60, [15, 0, 3, 3]).unwrap();
+        assert!(config.slots().eq([0, 3, 15]));
+        assert_eq!(config.interval(), 60);
+        assert!(config.is_running());
+        assert!(!SlideshowConfig::new(60, []).unwrap().is_running());
+    }
+
+    #[test]
+    fn slideshow_slot_out_of_range() {
+        assert_eq!(
+            SlideshowConfig::new(60, [1, 16, 17]).unwrap_err(),
+            SlotOutOfRange(16)
+        );
+    }
+}